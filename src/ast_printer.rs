@@ -23,6 +23,46 @@ impl Visitor<String> for AstPrinter {
     fn visit_literal(&self, literal: &expr::LiteralExpr) -> String {
         literal.value.to_string()
     }
+
+    fn visit_variable(&self, variable: &expr::VariableExpr) -> String {
+        variable.name.lexeme.to_string()
+    }
+
+    fn visit_logical(&self, logical: &expr::LogicalExpr) -> String {
+        let expr_vec = &[&*logical.left, &*logical.right];
+        AstPrinter::parenthesize(&logical.operator.lexeme, expr_vec, self)
+    }
+
+    fn visit_call(&self, call: &expr::CallExpr) -> String {
+        let mut expr_vec = vec![&*call.callee];
+        expr_vec.extend(call.arguments.iter());
+        AstPrinter::parenthesize("call", &expr_vec, self)
+    }
+
+    fn visit_get(&self, get: &expr::GetExpr) -> String {
+        AstPrinter::parenthesize(&format!(". {}", get.name.lexeme), &[&*get.object], self)
+    }
+
+    fn visit_set(&self, set: &expr::SetExpr) -> String {
+        let expr_vec = &[&*set.object, &*set.value];
+        AstPrinter::parenthesize(&format!(".= {}", set.name.lexeme), expr_vec, self)
+    }
+
+    fn visit_this(&self, _this: &expr::ThisExpr) -> String {
+        String::from("this")
+    }
+
+    fn visit_super(&self, super_expr: &expr::SuperExpr) -> String {
+        format!("(super {})", super_expr.method.lexeme)
+    }
+
+    fn visit_assign(&self, assign: &expr::AssignExpr) -> String {
+        AstPrinter::parenthesize(
+            &format!("= {}", assign.name.lexeme),
+            &[&*assign.value],
+            self,
+        )
+    }
 }
 
 impl AstPrinter {
@@ -54,6 +94,14 @@ impl AstPrinter {
             Expr::Grouping(grouping) => grouping.accept(visitor),
             Expr::Unary(unary) => unary.accept(visitor),
             Expr::Literal(literal) => literal.accept(visitor),
+            Expr::Variable(variable) => variable.accept(visitor),
+            Expr::Assign(assign) => assign.accept(visitor),
+            Expr::Logical(logical) => logical.accept(visitor),
+            Expr::Call(call) => call.accept(visitor),
+            Expr::Get(get) => get.accept(visitor),
+            Expr::Set(set) => set.accept(visitor),
+            Expr::This(this) => this.accept(visitor),
+            Expr::Super(super_expr) => super_expr.accept(visitor),
         }
     }
 }
@@ -0,0 +1,160 @@
+use std::rc::Rc;
+
+use crate::environment::Environment;
+use crate::error::ErrorCode;
+use crate::heap::{Gc, Trace, Tracer};
+use crate::interpreter::{Interpreter, RuntimeError, Unwind};
+use crate::stmt::{FunctionStmt, Stmt};
+use crate::symbol::Symbol;
+use crate::token::{Object, Token};
+
+/// How many arguments a callable takes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Arity {
+    Fixed(usize),
+    /// Any number of arguments.
+    Variadic,
+}
+
+pub trait LoxCallable {
+    fn arity(&self) -> Arity;
+    /// `paren` is the closing parenthesis of the call, where errors raised by
+    /// the callee itself rather than by its body are reported.
+    fn call(&self, interpreter: &Interpreter, paren: &Token, arguments: Vec<Object>) -> Result<Object, RuntimeError>;
+}
+
+pub struct LoxFunction {
+    name: Token,
+    params: Vec<Token>,
+    body: Rc<Vec<Stmt>>,
+    /// The environment the declaration was evaluated in. It is shared, not
+    /// copied, so later assignments to captured variables stay visible.
+    closure: Gc<Environment>,
+    is_initializer: bool,
+}
+
+impl LoxFunction {
+    pub fn new(declaration: &FunctionStmt, closure: Gc<Environment>, is_initializer: bool) -> Self {
+        LoxFunction {
+            name: declaration.name.clone(),
+            params: declaration.params.clone(),
+            body: Rc::clone(&declaration.body),
+            closure,
+            is_initializer,
+        }
+    }
+
+    /// Returns a copy of this method whose closure binds `this` to `instance`.
+    pub fn bind(&self, interpreter: &Interpreter, instance: Object) -> LoxFunction {
+        let mut environment = Environment::with_enclosing(self.closure);
+        environment.define(Symbol::intern("this"), instance);
+
+        LoxFunction {
+            name: self.name.clone(),
+            params: self.params.clone(),
+            body: Rc::clone(&self.body),
+            closure: interpreter.allocate(environment),
+            is_initializer: self.is_initializer,
+        }
+    }
+
+    fn this(&self, interpreter: &Interpreter) -> Object {
+        interpreter.heap().get(self.closure).value(&Symbol::intern("this")).expect("resolver binds 'this'")
+    }
+}
+
+impl LoxCallable for LoxFunction {
+    fn arity(&self) -> Arity {
+        Arity::Fixed(self.params.len())
+    }
+
+    fn call(&self, interpreter: &Interpreter, _paren: &Token, arguments: Vec<Object>) -> Result<Object, RuntimeError> {
+        let mut environment = Environment::with_enclosing(self.closure);
+        for (param, argument) in self.params.iter().zip(arguments) {
+            environment.define(param.lexeme.clone(), argument);
+        }
+
+        // An initializer always hands back the instance, even on an early `return;`.
+        match interpreter.execute_block(&self.body, interpreter.allocate(environment)) {
+            Ok(()) | Err(Unwind::Return(_)) if self.is_initializer => Ok(self.this(interpreter)),
+            Ok(()) => Ok(Object::Nil),
+            Err(Unwind::Return(value)) => Ok(value),
+            Err(Unwind::Error(error)) => Err(error.unwind_from(&self.name.lexeme)),
+        }
+    }
+}
+
+impl Trace for LoxFunction {
+    fn trace(&self, tracer: &mut Tracer) {
+        tracer.mark(self.closure);
+    }
+}
+
+impl std::fmt::Debug for LoxFunction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{self}")
+    }
+}
+
+impl std::fmt::Display for LoxFunction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "<fn {}>", self.name.lexeme)
+    }
+}
+
+/// The Rust side of a native function: it gets the arguments and returns the
+/// result, or the message of a runtime error. The collector doesn't trace
+/// into it; Lox values it keeps are pinned through `Rooted` handles instead.
+pub type NativeFn = dyn Fn(&[Object]) -> Result<Object, String>;
+
+/// A function provided by the host program rather than written in Lox.
+pub struct NativeFunction {
+    name: Symbol,
+    arity: Arity,
+    function: Box<NativeFn>,
+    /// The value a bound method was taken from. `function` holds it too, but
+    /// only this copy is visible to the collector.
+    receiver: Option<Object>,
+}
+
+impl NativeFunction {
+    pub fn new(name: Symbol, arity: Arity, function: Box<NativeFn>) -> Self {
+        NativeFunction { name, arity, function, receiver: None }
+    }
+
+    /// A native that is a method of `receiver`, which stays traced for as
+    /// long as the native is reachable.
+    pub fn bound(name: Symbol, arity: Arity, receiver: Object, function: Box<NativeFn>) -> Self {
+        NativeFunction { name, arity, function, receiver: Some(receiver) }
+    }
+}
+
+impl LoxCallable for NativeFunction {
+    fn arity(&self) -> Arity {
+        self.arity
+    }
+
+    fn call(&self, _interpreter: &Interpreter, paren: &Token, arguments: Vec<Object>) -> Result<Object, RuntimeError> {
+        (self.function)(&arguments).map_err(|message| RuntimeError::new(ErrorCode::NativeError, message, paren.clone()))
+    }
+}
+
+impl Trace for NativeFunction {
+    fn trace(&self, tracer: &mut Tracer) {
+        if let Some(receiver) = &self.receiver {
+            receiver.trace(tracer);
+        }
+    }
+}
+
+impl std::fmt::Debug for NativeFunction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{self}")
+    }
+}
+
+impl std::fmt::Display for NativeFunction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "<native fn {}>", self.name)
+    }
+}
@@ -0,0 +1,144 @@
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use crate::callable::{Arity, LoxCallable, LoxFunction};
+use crate::error::ErrorCode;
+use crate::heap::{Gc, Trace, Tracer};
+use crate::interpreter::{Interpreter, RuntimeError};
+use crate::symbol::Symbol;
+use crate::token::{Object, Token};
+
+pub struct LoxClass {
+    name: Symbol,
+    superclass: Option<Rc<LoxClass>>,
+    methods: HashMap<Symbol, Rc<LoxFunction>>,
+}
+
+impl LoxClass {
+    pub fn new(
+        name: Symbol,
+        superclass: Option<Rc<LoxClass>>,
+        methods: HashMap<Symbol, Rc<LoxFunction>>,
+    ) -> Self {
+        LoxClass { name, superclass, methods }
+    }
+
+    /// Looks the method up on this class, then along the superclass chain.
+    pub fn find_method(&self, name: &Symbol) -> Option<Rc<LoxFunction>> {
+        match self.methods.get(name) {
+            Some(method) => Some(Rc::clone(method)),
+            None => self.superclass.as_ref()?.find_method(name),
+        }
+    }
+}
+
+// Implemented on the `Rc` so that instances can keep a handle to their class.
+impl LoxCallable for Rc<LoxClass> {
+    fn arity(&self) -> Arity {
+        self.find_method(&Symbol::intern("init")).map_or(Arity::Fixed(0), |initializer| initializer.arity())
+    }
+
+    fn call(&self, interpreter: &Interpreter, paren: &Token, arguments: Vec<Object>) -> Result<Object, RuntimeError> {
+        let fields = interpreter.allocate(Fields::default());
+        let instance = Object::Instance(LoxInstance::new(Rc::clone(self), fields));
+
+        if let Some(initializer) = self.find_method(&Symbol::intern("init")) {
+            initializer.bind(interpreter, instance.clone()).call(interpreter, paren, arguments)?;
+        }
+
+        Ok(instance)
+    }
+}
+
+impl Trace for LoxClass {
+    fn trace(&self, tracer: &mut Tracer) {
+        self.methods.values().for_each(|method| method.trace(tracer));
+        if let Some(superclass) = &self.superclass {
+            superclass.trace(tracer);
+        }
+    }
+}
+
+impl std::fmt::Debug for LoxClass {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{self}")
+    }
+}
+
+impl std::fmt::Display for LoxClass {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "<class {}>", self.name)
+    }
+}
+
+/// A reference to an instance. Its class never changes, so it is kept in the
+/// reference; the fields are mutable and shared, so they live on the heap.
+#[derive(Clone)]
+pub struct LoxInstance {
+    class: Rc<LoxClass>,
+    fields: Gc<Fields>,
+}
+
+#[derive(Debug, Default)]
+pub struct Fields {
+    values: HashMap<Symbol, Object>,
+}
+
+impl LoxInstance {
+    pub fn new(class: Rc<LoxClass>, fields: Gc<Fields>) -> Self {
+        LoxInstance { class, fields }
+    }
+
+    /// Fields shadow methods; methods come back bound to this instance.
+    pub fn get(&self, interpreter: &Interpreter, name: &Token) -> Result<Object, RuntimeError> {
+        let field = interpreter.heap().get(self.fields).values.get(&name.lexeme).cloned();
+        if let Some(value) = field {
+            return Ok(value);
+        }
+
+        match self.class.find_method(&name.lexeme) {
+            Some(method) => {
+                let bound = method.bind(interpreter, Object::Instance(self.clone()));
+                Ok(Object::Function(Rc::new(bound)))
+            }
+            None => Err(RuntimeError::new(
+                ErrorCode::UndefinedProperty,
+                format!("Undefined property '{}'.", name.lexeme),
+                name.clone(),
+            )),
+        }
+    }
+
+    pub fn set(&self, interpreter: &Interpreter, name: &Token, value: Object) {
+        interpreter.heap_mut().get_mut(self.fields).values.insert(name.lexeme.clone(), value);
+    }
+
+    pub fn ptr_eq(&self, other: &LoxInstance) -> bool {
+        self.fields == other.fields
+    }
+}
+
+impl Trace for LoxInstance {
+    fn trace(&self, tracer: &mut Tracer) {
+        self.class.trace(tracer);
+        tracer.mark(self.fields);
+    }
+}
+
+impl Trace for Fields {
+    fn trace(&self, tracer: &mut Tracer) {
+        self.values.values().for_each(|value| value.trace(tracer));
+    }
+}
+
+impl std::fmt::Debug for LoxInstance {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{self}")
+    }
+}
+
+impl std::fmt::Display for LoxInstance {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "<{} instance>", self.class.name)
+    }
+}
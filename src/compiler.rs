@@ -0,0 +1,649 @@
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use crate::chunk::{Constant, Function, OpCode};
+use crate::error::{ErrorCode, Lox, Reporter};
+use crate::expr::{self, Expr};
+use crate::stmt::{self, Stmt};
+use crate::symbol::Symbol;
+use crate::token::Object;
+use crate::token_type::TokenType;
+
+const MAX_LOCALS: usize = 256;
+const MAX_UPVALUES: usize = 256;
+
+/// Compiles a resolved program into bytecode for the `Vm`. Local variables
+/// live in stack slots and captured ones are reached through upvalues, so the
+/// compiler tracks its own scopes instead of the resolver's hop counts.
+pub struct Compiler<'a> {
+    reporter: &'a Reporter,
+    /// The innermost function being compiled is last.
+    functions: RefCell<Vec<FunctionCompiler>>,
+    /// Line of the token most recently visited, recorded for every byte emitted.
+    line: Cell<usize>,
+    had_error: Cell<bool>,
+}
+
+pub struct LoxCompileError;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FunctionKind {
+    Script,
+    Function,
+    Method,
+    Initializer,
+}
+
+struct FunctionCompiler {
+    function: Function,
+    kind: FunctionKind,
+    locals: Vec<Local>,
+    upvalues: Vec<Upvalue>,
+    scope_depth: usize,
+    identifiers: HashMap<Symbol, u16>,
+}
+
+struct Local {
+    name: Symbol,
+    /// `None` while the variable's initializer is being compiled.
+    depth: Option<usize>,
+    is_captured: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Upvalue {
+    index: u8,
+    is_local: bool,
+}
+
+impl FunctionCompiler {
+    fn new(name: Option<String>, kind: FunctionKind) -> Self {
+        // Slot zero holds the callee itself, or the receiver inside methods.
+        let receiver = match kind {
+            FunctionKind::Method | FunctionKind::Initializer => "this",
+            FunctionKind::Script | FunctionKind::Function => "",
+        };
+
+        FunctionCompiler {
+            function: Function { name, ..Function::default() },
+            kind,
+            locals: vec![Local { name: Symbol::intern(receiver), depth: Some(0), is_captured: false }],
+            upvalues: Vec::new(),
+            scope_depth: 0,
+            identifiers: HashMap::new(),
+        }
+    }
+
+    fn resolve_local(&self, name: &Symbol) -> Option<u8> {
+        self.locals
+            .iter()
+            .rposition(|local| local.name == *name)
+            .map(|slot| slot as u8)
+    }
+}
+
+impl expr::Visitor<()> for Compiler<'_> {
+    fn visit_binary(&self, binary: &expr::BinaryExpr) {
+        self.expression(&binary.left);
+        self.expression(&binary.right);
+        self.line.set(binary.operator.line);
+
+        match binary.operator.ttype {
+            TokenType::Plus => self.emit_op(OpCode::Add),
+            TokenType::Minus => self.emit_op(OpCode::Subtract),
+            TokenType::Star => self.emit_op(OpCode::Multiply),
+            TokenType::Slash => self.emit_op(OpCode::Divide),
+            TokenType::Exponent => self.emit_op(OpCode::Power),
+            TokenType::Greater => self.emit_op(OpCode::Greater),
+            TokenType::GreaterEqual => self.emit_op(OpCode::GreaterEqual),
+            TokenType::Less => self.emit_op(OpCode::Less),
+            TokenType::LessEqual => self.emit_op(OpCode::LessEqual),
+            TokenType::Equals => self.emit_op(OpCode::Equal),
+            TokenType::BangEqual => {
+                self.emit_op(OpCode::Equal);
+                self.emit_op(OpCode::Not);
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    fn visit_grouping(&self, grouping: &expr::GroupingExpr) {
+        self.expression(&grouping.expr);
+    }
+
+    fn visit_unary(&self, unary: &expr::UnaryExpr) {
+        self.expression(&unary.right);
+        self.line.set(unary.operator.line);
+
+        match unary.operator.ttype {
+            TokenType::Minus => self.emit_op(OpCode::Negate),
+            TokenType::Bang => self.emit_op(OpCode::Not),
+            _ => unreachable!(),
+        }
+    }
+
+    fn visit_literal(&self, literal: &expr::LiteralExpr) {
+        match &literal.value {
+            Object::Num(num) => self.emit_constant(Constant::Number(*num)),
+            Object::Str(str) => self.emit_constant(Constant::Str(str.clone())),
+            Object::Boolean(true) => self.emit_op(OpCode::True),
+            Object::Boolean(false) => self.emit_op(OpCode::False),
+            Object::Nil => self.emit_op(OpCode::Nil),
+            _ => unreachable!("The parser only produces primitive literals."),
+        }
+    }
+
+    fn visit_variable(&self, variable: &expr::VariableExpr) {
+        self.line.set(variable.name.line);
+        self.named_variable(&variable.name.lexeme, None);
+    }
+
+    fn visit_assign(&self, assign: &expr::AssignExpr) {
+        self.line.set(assign.name.line);
+        self.named_variable(&assign.name.lexeme, Some(&assign.value));
+    }
+
+    fn visit_logical(&self, logical: &expr::LogicalExpr) {
+        self.expression(&logical.left);
+        self.line.set(logical.operator.line);
+
+        match logical.operator.ttype {
+            TokenType::And => {
+                let end_jump = self.emit_jump(OpCode::JumpIfFalse);
+                self.emit_op(OpCode::Pop);
+                self.expression(&logical.right);
+                self.patch_jump(end_jump);
+            }
+            TokenType::Or => {
+                let else_jump = self.emit_jump(OpCode::JumpIfFalse);
+                let end_jump = self.emit_jump(OpCode::Jump);
+                self.patch_jump(else_jump);
+                self.emit_op(OpCode::Pop);
+                self.expression(&logical.right);
+                self.patch_jump(end_jump);
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    fn visit_call(&self, call: &expr::CallExpr) {
+        self.expression(&call.callee);
+        for argument in &call.arguments {
+            self.expression(argument);
+        }
+
+        self.line.set(call.paren.line);
+        self.emit_op_u8(OpCode::Call, call.arguments.len() as u8);
+    }
+
+    fn visit_get(&self, get: &expr::GetExpr) {
+        self.expression(&get.object);
+        self.line.set(get.name.line);
+        let name = self.identifier_constant(&get.name.lexeme);
+        self.emit_op_u16(OpCode::GetProperty, name);
+    }
+
+    fn visit_set(&self, set: &expr::SetExpr) {
+        self.expression(&set.object);
+        self.expression(&set.value);
+        self.line.set(set.name.line);
+        let name = self.identifier_constant(&set.name.lexeme);
+        self.emit_op_u16(OpCode::SetProperty, name);
+    }
+
+    fn visit_this(&self, this: &expr::ThisExpr) {
+        self.line.set(this.keyword.line);
+        self.named_variable(&Symbol::intern("this"), None);
+    }
+
+    fn visit_super(&self, super_expr: &expr::SuperExpr) {
+        self.line.set(super_expr.keyword.line);
+        self.named_variable(&Symbol::intern("this"), None);
+        self.named_variable(&Symbol::intern("super"), None);
+        let name = self.identifier_constant(&super_expr.method.lexeme);
+        self.emit_op_u16(OpCode::GetSuper, name);
+    }
+}
+
+impl stmt::Visitor<()> for Compiler<'_> {
+    fn visit_expression(&self, stmt: &stmt::ExpressionStmt) {
+        self.expression(&stmt.expr);
+        self.emit_op(OpCode::Pop);
+    }
+
+    fn visit_print(&self, stmt: &stmt::PrintStmt) {
+        self.expression(&stmt.expr);
+        self.emit_op(OpCode::Print);
+    }
+
+    fn visit_var(&self, stmt: &stmt::VarStmt) {
+        self.line.set(stmt.name.line);
+        self.declare_variable(&stmt.name.lexeme);
+
+        match &stmt.initializer {
+            Some(initializer) => self.expression(initializer),
+            None => self.emit_op(OpCode::Nil),
+        }
+
+        self.define_variable(&stmt.name.lexeme);
+    }
+
+    fn visit_block(&self, stmt: &stmt::BlockStmt) {
+        self.begin_scope();
+        self.statements(&stmt.statements);
+        self.end_scope();
+    }
+
+    fn visit_if(&self, stmt: &stmt::IfStmt) {
+        self.expression(&stmt.condition);
+
+        let then_jump = self.emit_jump(OpCode::JumpIfFalse);
+        self.emit_op(OpCode::Pop);
+        self.statement(&stmt.then_branch);
+
+        let else_jump = self.emit_jump(OpCode::Jump);
+        self.patch_jump(then_jump);
+        self.emit_op(OpCode::Pop);
+
+        if let Some(else_branch) = &stmt.else_branch {
+            self.statement(else_branch);
+        }
+        self.patch_jump(else_jump);
+    }
+
+    fn visit_while(&self, stmt: &stmt::WhileStmt) {
+        let loop_start = self.current_offset();
+        self.expression(&stmt.condition);
+
+        let exit_jump = self.emit_jump(OpCode::JumpIfFalse);
+        self.emit_op(OpCode::Pop);
+        self.statement(&stmt.body);
+        self.emit_loop(loop_start);
+
+        self.patch_jump(exit_jump);
+        self.emit_op(OpCode::Pop);
+    }
+
+    fn visit_function(&self, stmt: &stmt::FunctionStmt) {
+        self.line.set(stmt.name.line);
+        self.declare_variable(&stmt.name.lexeme);
+        // Initialized before the body is compiled so the function can recurse.
+        self.mark_initialized();
+        self.function(stmt, FunctionKind::Function);
+        self.define_variable(&stmt.name.lexeme);
+    }
+
+    fn visit_return(&self, stmt: &stmt::ReturnStmt) {
+        self.line.set(stmt.keyword.line);
+
+        match &stmt.value {
+            Some(value) => {
+                self.expression(value);
+                self.emit_op(OpCode::Return);
+            }
+            None => self.emit_return(),
+        }
+    }
+
+    fn visit_class(&self, stmt: &stmt::ClassStmt) {
+        self.line.set(stmt.name.line);
+        let name = self.identifier_constant(&stmt.name.lexeme);
+        self.declare_variable(&stmt.name.lexeme);
+        self.emit_op_u16(OpCode::Class, name);
+        self.define_variable(&stmt.name.lexeme);
+
+        if let Some(superclass) = &stmt.superclass {
+            superclass.accept(self);
+
+            // The superclass stays on the stack as the local `super` that
+            // methods capture.
+            self.begin_scope();
+            self.add_local(&Symbol::intern("super"));
+            self.mark_initialized();
+
+            self.named_variable(&stmt.name.lexeme, None);
+            self.emit_op(OpCode::Inherit);
+        }
+
+        self.named_variable(&stmt.name.lexeme, None);
+        for method in &stmt.methods {
+            self.line.set(method.name.line);
+            let kind = if method.name.lexeme == "init" {
+                FunctionKind::Initializer
+            } else {
+                FunctionKind::Method
+            };
+            self.function(method, kind);
+
+            let name = self.identifier_constant(&method.name.lexeme);
+            self.emit_op_u16(OpCode::Method, name);
+        }
+        self.emit_op(OpCode::Pop);
+
+        if stmt.superclass.is_some() {
+            self.end_scope();
+        }
+    }
+}
+
+impl<'a> Compiler<'a> {
+    pub fn new(reporter: &'a Reporter) -> Self {
+        Compiler {
+            reporter,
+            functions: RefCell::new(vec![FunctionCompiler::new(None, FunctionKind::Script)]),
+            line: Cell::new(1),
+            had_error: Cell::new(false),
+        }
+    }
+
+    pub fn compile(self, statements: &[Stmt]) -> Result<Rc<Function>, LoxCompileError> {
+        self.statements(statements);
+        self.emit_return();
+
+        if self.had_error.get() {
+            return Err(LoxCompileError);
+        }
+
+        let script = self.functions.into_inner().pop().expect("The script compiler is never popped.");
+        Ok(Rc::new(script.function))
+    }
+
+    fn statements(&self, statements: &[Stmt]) {
+        for statement in statements {
+            self.statement(statement);
+        }
+    }
+
+    fn statement(&self, stmt: &Stmt) {
+        match stmt {
+            Stmt::Expression(stmt) => stmt.accept(self),
+            Stmt::Print(stmt) => stmt.accept(self),
+            Stmt::Var(stmt) => stmt.accept(self),
+            Stmt::Block(stmt) => stmt.accept(self),
+            Stmt::If(stmt) => stmt.accept(self),
+            Stmt::While(stmt) => stmt.accept(self),
+            Stmt::Function(stmt) => stmt.accept(self),
+            Stmt::Return(stmt) => stmt.accept(self),
+            Stmt::Class(stmt) => stmt.accept(self),
+        }
+    }
+
+    fn expression(&self, expr: &Expr) {
+        match expr {
+            Expr::Binary(expr) => expr.accept(self),
+            Expr::Grouping(expr) => expr.accept(self),
+            Expr::Unary(expr) => expr.accept(self),
+            Expr::Literal(expr) => expr.accept(self),
+            Expr::Variable(expr) => expr.accept(self),
+            Expr::Assign(expr) => expr.accept(self),
+            Expr::Logical(expr) => expr.accept(self),
+            Expr::Call(expr) => expr.accept(self),
+            Expr::Get(expr) => expr.accept(self),
+            Expr::Set(expr) => expr.accept(self),
+            Expr::This(expr) => expr.accept(self),
+            Expr::Super(expr) => expr.accept(self),
+        }
+    }
+
+    fn function(&self, declaration: &stmt::FunctionStmt, kind: FunctionKind) {
+        let name = declaration.name.lexeme.to_string();
+        self.functions.borrow_mut().push(FunctionCompiler::new(Some(name), kind));
+
+        self.begin_scope();
+        for param in &declaration.params {
+            self.declare_variable(&param.lexeme);
+            self.mark_initialized();
+        }
+        self.statements(&declaration.body);
+        self.emit_return();
+
+        let compiled = self.functions.borrow_mut().pop().expect("Function compiler stack underflow.");
+        let mut function = compiled.function;
+        function.arity = declaration.params.len();
+        function.upvalue_count = compiled.upvalues.len();
+
+        let constant = self.make_constant(Constant::Function(Rc::new(function)));
+        self.emit_op_u16(OpCode::Closure, constant);
+        for upvalue in compiled.upvalues {
+            self.emit_byte(upvalue.is_local as u8);
+            self.emit_byte(upvalue.index);
+        }
+    }
+
+    /// Emits a load of `name`, or a store of `value` into it when one is given.
+    fn named_variable(&self, name: &Symbol, value: Option<&Expr>) {
+        let level = self.functions.borrow().len() - 1;
+        let local = self.current(|function| function.resolve_local(name));
+
+        let (get, set, operand) = if let Some(slot) = local {
+            (OpCode::GetLocal, OpCode::SetLocal, slot as u16)
+        } else if let Some(index) = self.resolve_upvalue(level, name) {
+            (OpCode::GetUpvalue, OpCode::SetUpvalue, index as u16)
+        } else {
+            let index = self.identifier_constant(name);
+            (OpCode::GetGlobal, OpCode::SetGlobal, index)
+        };
+
+        let op = match value {
+            Some(value) => {
+                self.expression(value);
+                set
+            }
+            None => get,
+        };
+
+        match op {
+            OpCode::GetGlobal | OpCode::SetGlobal => self.emit_op_u16(op, operand),
+            _ => self.emit_op_u8(op, operand as u8),
+        }
+    }
+
+    fn resolve_upvalue(&self, level: usize, name: &Symbol) -> Option<u8> {
+        if level == 0 {
+            return None;
+        }
+
+        let local = self.functions.borrow()[level - 1].resolve_local(name);
+        if let Some(slot) = local {
+            self.functions.borrow_mut()[level - 1].locals[slot as usize].is_captured = true;
+            return Some(self.add_upvalue(level, slot, true));
+        }
+
+        let upvalue = self.resolve_upvalue(level - 1, name)?;
+        Some(self.add_upvalue(level, upvalue, false))
+    }
+
+    fn add_upvalue(&self, level: usize, index: u8, is_local: bool) -> u8 {
+        let upvalue = Upvalue { index, is_local };
+        let mut functions = self.functions.borrow_mut();
+        let upvalues = &mut functions[level].upvalues;
+
+        if let Some(existing) = upvalues.iter().position(|candidate| *candidate == upvalue) {
+            return existing as u8;
+        }
+
+        if upvalues.len() == MAX_UPVALUES {
+            drop(functions);
+            self.error(ErrorCode::TooManyUpvalues, "Too many closure variables in function.");
+            return 0;
+        }
+
+        upvalues.push(upvalue);
+        (upvalues.len() - 1) as u8
+    }
+
+    fn declare_variable(&self, name: &Symbol) {
+        if self.current(|function| function.scope_depth) == 0 {
+            return;
+        }
+
+        self.add_local(name);
+    }
+
+    fn add_local(&self, name: &Symbol) {
+        if self.current(|function| function.locals.len()) == MAX_LOCALS {
+            self.error(ErrorCode::TooManyLocals, "Too many local variables in function.");
+            return;
+        }
+
+        self.current_mut(|function| {
+            function.locals.push(Local { name: name.clone(), depth: None, is_captured: false })
+        });
+    }
+
+    fn define_variable(&self, name: &Symbol) {
+        if self.current(|function| function.scope_depth) > 0 {
+            self.mark_initialized();
+            return;
+        }
+
+        let global = self.identifier_constant(name);
+        self.emit_op_u16(OpCode::DefineGlobal, global);
+    }
+
+    fn mark_initialized(&self) {
+        self.current_mut(|function| {
+            if function.scope_depth == 0 {
+                return;
+            }
+            let depth = function.scope_depth;
+            if let Some(local) = function.locals.last_mut() {
+                local.depth = Some(depth);
+            }
+        });
+    }
+
+    fn begin_scope(&self) {
+        self.current_mut(|function| function.scope_depth += 1);
+    }
+
+    fn end_scope(&self) {
+        let depth = self.current_mut(|function| {
+            function.scope_depth -= 1;
+            function.scope_depth
+        });
+
+        loop {
+            let captured = self.current_mut(|function| match function.locals.last() {
+                Some(local) if local.depth.is_none_or(|local_depth| local_depth > depth) => {
+                    let captured = local.is_captured;
+                    function.locals.pop();
+                    Some(captured)
+                }
+                _ => None,
+            });
+
+            match captured {
+                Some(true) => self.emit_op(OpCode::CloseUpvalue),
+                Some(false) => self.emit_op(OpCode::Pop),
+                None => break,
+            }
+        }
+    }
+
+    fn identifier_constant(&self, name: &Symbol) -> u16 {
+        if let Some(index) = self.current(|function| function.identifiers.get(name).copied()) {
+            return index;
+        }
+
+        let index = self.make_constant(Constant::Str(name.clone()));
+        self.current_mut(|function| function.identifiers.insert(name.clone(), index));
+        index
+    }
+
+    fn make_constant(&self, constant: Constant) -> u16 {
+        let index = self.current_mut(|function| function.function.chunk.add_constant(constant));
+
+        match u16::try_from(index) {
+            Ok(index) => index,
+            Err(_) => {
+                self.error(ErrorCode::TooManyConstants, "Too many constants in one chunk.");
+                0
+            }
+        }
+    }
+
+    fn emit_constant(&self, constant: Constant) {
+        let index = self.make_constant(constant);
+        self.emit_op_u16(OpCode::Constant, index);
+    }
+
+    fn emit_return(&self) {
+        if self.current(|function| function.kind) == FunctionKind::Initializer {
+            self.emit_op_u8(OpCode::GetLocal, 0);
+        } else {
+            self.emit_op(OpCode::Nil);
+        }
+        self.emit_op(OpCode::Return);
+    }
+
+    fn emit_jump(&self, op: OpCode) -> usize {
+        self.emit_op_u16(op, u16::MAX);
+        self.current_offset() - 2
+    }
+
+    /// Back-patches the operand at `offset` to land just past the code emitted so far.
+    fn patch_jump(&self, offset: usize) {
+        let jump = self.current_offset() - offset - 2;
+
+        let Ok(jump) = u16::try_from(jump) else {
+            self.error(ErrorCode::JumpTooLarge, "Too much code to jump over.");
+            return;
+        };
+
+        self.current_mut(|function| {
+            let code = &mut function.function.chunk.code;
+            code[offset..offset + 2].copy_from_slice(&jump.to_be_bytes());
+        });
+    }
+
+    fn emit_loop(&self, loop_start: usize) {
+        let offset = self.current_offset() - loop_start + 3;
+
+        let Ok(offset) = u16::try_from(offset) else {
+            self.error(ErrorCode::LoopTooLarge, "Loop body too large.");
+            return;
+        };
+
+        self.emit_op_u16(OpCode::Loop, offset);
+    }
+
+    fn current_offset(&self) -> usize {
+        self.current(|function| function.function.chunk.code.len())
+    }
+
+    fn emit_op(&self, op: OpCode) {
+        self.emit_byte(op as u8);
+    }
+
+    fn emit_op_u8(&self, op: OpCode, operand: u8) {
+        self.emit_byte(op as u8);
+        self.emit_byte(operand);
+    }
+
+    fn emit_op_u16(&self, op: OpCode, operand: u16) {
+        self.emit_byte(op as u8);
+        for byte in operand.to_be_bytes() {
+            self.emit_byte(byte);
+        }
+    }
+
+    fn emit_byte(&self, byte: u8) {
+        let line = self.line.get();
+        self.current_mut(|function| function.function.chunk.write(byte, line));
+    }
+
+    fn current<T>(&self, f: impl FnOnce(&FunctionCompiler) -> T) -> T {
+        f(self.functions.borrow().last().expect("No function is being compiled."))
+    }
+
+    fn current_mut<T>(&self, f: impl FnOnce(&mut FunctionCompiler) -> T) -> T {
+        f(self.functions.borrow_mut().last_mut().expect("No function is being compiled."))
+    }
+
+    fn error(&self, code: ErrorCode, message: &str) {
+        Lox::error(code, self.line.get(), message.to_string()).report(self.reporter);
+        self.had_error.set(true);
+    }
+}
@@ -0,0 +1,173 @@
+use std::fmt::Write;
+
+use crate::chunk::{Chunk, Constant, Function, OpCode};
+
+/// Lists `function` and, after it, every function nested in its constants.
+pub fn disassemble_function(function: &Function) -> String {
+    let mut out = disassemble_chunk(&function.chunk, &function.to_string());
+
+    for constant in &function.chunk.constants {
+        if let Constant::Function(nested) = constant {
+            out.push('\n');
+            out.push_str(&disassemble_function(nested));
+        }
+    }
+
+    out
+}
+
+pub fn disassemble_chunk(chunk: &Chunk, name: &str) -> String {
+    let mut out = format!("== {name} ==\n");
+
+    let mut offset = 0;
+    while offset < chunk.code.len() {
+        let (text, next) = disassemble_instruction(chunk, offset);
+        out.push_str(&text);
+        out.push('\n');
+        offset = next;
+    }
+
+    out
+}
+
+/// Formats the instruction at `offset` as `offset line name operands` and
+/// returns it with the offset of the following instruction.
+pub fn disassemble_instruction(chunk: &Chunk, offset: usize) -> (String, usize) {
+    let mut out = format!("{offset:04} ");
+
+    if offset > 0 && chunk.lines[offset] == chunk.lines[offset - 1] {
+        out.push_str("   | ");
+    } else {
+        let _ = write!(out, "{:4} ", chunk.lines[offset]);
+    }
+
+    let byte = chunk.code[offset];
+    let Some(op) = OpCode::from_byte(byte) else {
+        let _ = write!(out, "Unknown opcode {byte}");
+        return (out, offset + 1);
+    };
+
+    let name = format!("{op:?}");
+    let next = match op {
+        OpCode::Constant
+        | OpCode::GetGlobal
+        | OpCode::DefineGlobal
+        | OpCode::SetGlobal
+        | OpCode::GetProperty
+        | OpCode::SetProperty
+        | OpCode::GetSuper
+        | OpCode::Class
+        | OpCode::Method => {
+            let index = chunk.read_u16(offset + 1);
+            let _ = write!(out, "{name:<16} {index:4} '{}'", constant_text(&chunk.constants[index as usize]));
+            offset + 3
+        }
+        OpCode::GetLocal | OpCode::SetLocal => {
+            let _ = write!(out, "{name:<16} slot {}", chunk.code[offset + 1]);
+            offset + 2
+        }
+        OpCode::GetUpvalue | OpCode::SetUpvalue => {
+            let _ = write!(out, "{name:<16} upvalue {}", chunk.code[offset + 1]);
+            offset + 2
+        }
+        OpCode::Call => {
+            let _ = write!(out, "{name:<16} {} args", chunk.code[offset + 1]);
+            offset + 2
+        }
+        OpCode::Jump | OpCode::JumpIfFalse => {
+            let target = offset + 3 + chunk.read_u16(offset + 1) as usize;
+            let _ = write!(out, "{name:<16} {offset:4} -> {target}");
+            offset + 3
+        }
+        OpCode::Loop => {
+            let target = offset + 3 - chunk.read_u16(offset + 1) as usize;
+            let _ = write!(out, "{name:<16} {offset:4} -> {target}");
+            offset + 3
+        }
+        OpCode::Closure => {
+            let index = chunk.read_u16(offset + 1);
+            let constant = &chunk.constants[index as usize];
+            let _ = write!(out, "{name:<16} {index:4} {}", constant_text(constant));
+
+            let Constant::Function(function) = constant else {
+                return (out, offset + 3);
+            };
+
+            let mut next = offset + 3;
+            for _ in 0..function.upvalue_count {
+                let kind = if chunk.code[next] == 1 { "local" } else { "upvalue" };
+                let index = chunk.code[next + 1];
+                let _ = write!(out, "\n{next:04}    |                     {kind} {index}");
+                next += 2;
+            }
+            next
+        }
+        _ => {
+            out.push_str(&name);
+            offset + 1
+        }
+    };
+
+    (out, next)
+}
+
+fn constant_text(constant: &Constant) -> String {
+    match constant {
+        Constant::Number(num) => num.to_string(),
+        Constant::Str(s) => s.to_string(),
+        Constant::Function(function) => function.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::Reporter;
+    use crate::compiler::Compiler;
+    use crate::parser::Parser;
+    use crate::scanner::Scanner;
+
+    fn compile(source: &str) -> Function {
+        let tokens = Scanner::new(source.as_bytes(), &Reporter::default()).scan_tokens().expect("test program should scan");
+        let statements = Parser::new(tokens, &Reporter::default()).parse().expect("test program should parse");
+        let Ok(function) = Compiler::new(&Reporter::default()).compile(&statements) else {
+            panic!("test program should compile");
+        };
+        std::rc::Rc::try_unwrap(function).expect("the script is not shared yet")
+    }
+
+    #[test]
+    fn lists_constants_with_their_values_and_lines() {
+        let listing = disassemble_function(&compile("print 1;\nvar a = \"x\";"));
+
+        assert_eq!(
+            listing,
+            "== <script> ==\n\
+             0000    1 Constant            0 '1'\n\
+             0003    | Print\n\
+             0004    2 Constant            1 'x'\n\
+             0007    | DefineGlobal        2 'a'\n\
+             0010    | Nil\n\
+             0011    | Return\n"
+        );
+    }
+
+    #[test]
+    fn decodes_jump_targets_and_local_slots() {
+        let listing = disassemble_function(&compile("{ var a = true; while (a) a = false; }"));
+
+        assert!(listing.contains("GetLocal         slot 1"));
+        assert!(listing.contains("JumpIfFalse         3 -> 14"));
+        assert!(listing.contains("Loop               11 -> 1"));
+    }
+
+    #[test]
+    fn lists_nested_functions_and_their_captures() {
+        let listing = disassemble_function(&compile("fun outer() { var x; fun inner() { return x; } }"));
+
+        assert!(listing.contains("== <fn outer> =="));
+        assert!(listing.contains("== <fn inner> =="));
+        assert!(listing.contains("local 1"));
+        assert!(listing.contains("GetUpvalue       upvalue 0"));
+    }
+}
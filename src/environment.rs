@@ -0,0 +1,110 @@
+use std::collections::HashMap;
+
+use crate::error::ErrorCode;
+use crate::heap::{Gc, Heap, Trace, Tracer};
+use crate::interpreter::RuntimeError;
+use crate::symbol::Symbol;
+use crate::token::{Object, Token};
+
+#[derive(Debug, Default)]
+pub struct Environment {
+    values: HashMap<Symbol, Object>,
+    enclosing: Option<Gc<Environment>>,
+}
+
+impl Environment {
+    pub fn new() -> Self {
+        Environment::default()
+    }
+
+    pub fn with_enclosing(enclosing: Gc<Environment>) -> Self {
+        Environment {
+            values: HashMap::new(),
+            enclosing: Some(enclosing),
+        }
+    }
+
+    pub fn define(&mut self, name: Symbol, value: Object) {
+        self.values.insert(name, value);
+    }
+
+    /// Looks a name up in this scope only, without walking the chain.
+    pub fn value(&self, name: &Symbol) -> Option<Object> {
+        self.values.get(name).cloned()
+    }
+
+    pub fn get(heap: &Heap, environment: Gc<Environment>, name: &Token) -> Result<Object, RuntimeError> {
+        let scope = heap.get(environment);
+        if let Some(value) = scope.values.get(&name.lexeme) {
+            return Ok(value.clone());
+        }
+
+        match scope.enclosing {
+            Some(enclosing) => Environment::get(heap, enclosing, name),
+            None => Err(Environment::undefined(name)),
+        }
+    }
+
+    pub fn assign(heap: &mut Heap, environment: Gc<Environment>, name: &Token, value: Object) -> Result<(), RuntimeError> {
+        let scope = heap.get_mut(environment);
+        if let Some(slot) = scope.values.get_mut(&name.lexeme) {
+            *slot = value;
+            return Ok(());
+        }
+
+        match scope.enclosing {
+            Some(enclosing) => Environment::assign(heap, enclosing, name, value),
+            None => Err(Environment::undefined(name)),
+        }
+    }
+
+    pub fn get_at(heap: &Heap, environment: Gc<Environment>, distance: usize, name: &Token) -> Result<Object, RuntimeError> {
+        match heap.get(Environment::ancestor(heap, environment, distance)).values.get(&name.lexeme) {
+            Some(value) => Ok(value.clone()),
+            None => Err(Environment::undefined(name)),
+        }
+    }
+
+    pub fn assign_at(
+        heap: &mut Heap,
+        environment: Gc<Environment>,
+        distance: usize,
+        name: &Token,
+        value: Object,
+    ) -> Result<(), RuntimeError> {
+        let ancestor = Environment::ancestor(heap, environment, distance);
+        match heap.get_mut(ancestor).values.get_mut(&name.lexeme) {
+            Some(slot) => {
+                *slot = value;
+                Ok(())
+            }
+            None => Err(Environment::undefined(name)),
+        }
+    }
+
+    /// Walks `distance` hops up the chain, as computed by the resolver.
+    pub fn ancestor(heap: &Heap, environment: Gc<Environment>, distance: usize) -> Gc<Environment> {
+        let mut environment = environment;
+        for _ in 0..distance {
+            environment = heap
+                .get(environment)
+                .enclosing
+                .expect("Resolved depth exceeds the environment chain.");
+        }
+        environment
+    }
+
+    fn undefined(name: &Token) -> RuntimeError {
+        RuntimeError::new(ErrorCode::UndefinedVariable, format!("Undefined variable '{}'.", name.lexeme), name.clone())
+            .with_help(format!("declare it with 'var {}' before using it", name.lexeme))
+    }
+}
+
+impl Trace for Environment {
+    fn trace(&self, tracer: &mut Tracer) {
+        if let Some(enclosing) = self.enclosing {
+            tracer.mark(enclosing);
+        }
+        self.values.values().for_each(|value| value.trace(tracer));
+    }
+}
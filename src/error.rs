@@ -1,32 +1,609 @@
-use crate::{token::Token, token_type::TokenType};
+//! Error reporting for every phase of rlox.
+//!
+//! Diagnostics are rendered for people by default. With
+//! `--error-format=json` each one is instead printed to stderr as a single
+//! line holding a JSON object:
+//!
+//! ```json
+//! {"code":"E0405","severity":"error","message":"Undefined variable 'x'.",
+//!  "file":"script.lox","start":{"line":1,"column":7},"end":{"line":1,"column":8},
+//!  "label":null,"help":"declare it with 'var x' before using it",
+//!  "trace":[{"function":"script","file":"script.lox","line":1}]}
+//! ```
+//!
+//! `severity` is always `"error"` today. Columns are 1-based and count
+//! characters; `end` is exclusive. The bytecode VM only tracks lines, so its
+//! runtime errors have `null` columns and an `end` equal to `start`.
+//!
+//! Runtime errors also carry a `"trace"`: the calls active when the error
+//! happened, innermost first, each as `{"function":"f","file":"script.lox",
+//! "line":3}`. Top-level code is the function `script`. The trace is empty
+//! for errors found before the program runs.
+//!
+//! The `code` is stable across releases, unlike the message text, so tools
+//! should match on it. Codes are grouped by the phase that reports them and
+//! listed on [`ErrorCode`].
 
+use std::io::IsTerminal;
+
+use crate::token::{Location, Span, Token};
+use crate::token_type::TokenType;
+
+/// The script being run, so diagnostics can name and quote it.
 #[derive(Debug)]
-pub struct Lox {
-    line: usize,
+struct Source {
+    name: String,
+    /// `None` when running compiled bytecode, which has no source text.
+    text: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ErrorFormat {
+    #[default]
+    Human,
+    Json,
+}
+
+/// Writes the diagnostics for one script to stderr, in the format its
+/// session chose. Every phase that runs the script reports through it.
+#[derive(Debug)]
+pub struct Reporter {
+    format: ErrorFormat,
+    source: Source,
+}
+
+impl Default for Reporter {
+    fn default() -> Self {
+        Reporter::new(ErrorFormat::Human, "<script>", None)
+    }
+}
+
+impl Reporter {
+    /// `text` is the script's source, when there is any to quote.
+    pub fn new(format: ErrorFormat, name: &str, text: Option<&str>) -> Self {
+        Reporter {
+            format,
+            source: Source { name: name.to_string(), text: text.map(str::to_string) },
+        }
+    }
+}
+
+/// Runs of identical frames longer than this are cut short in stack traces.
+const MAX_REPEATED_FRAMES: usize = 3;
+
+/// A call that was active when a runtime error happened: the function's name,
+/// or `script` for top-level code, and the line it had reached.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceFrame {
+    pub function: String,
+    pub line: usize,
+}
+
+/// Stable identifiers for everything rlox reports. New codes may be added,
+/// but a code's meaning never changes and codes are never reused.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorCode {
+    // E00xx: scanning.
+    /// `E0001`: a character that can't start any token.
+    UnexpectedCharacter,
+    /// `E0002`: a string literal still open at the end of the file.
+    UnterminatedString,
+
+    // E01xx: parsing.
+    /// `E0100`: a specific token, such as `;` or `)`, was required.
+    ExpectedToken,
+    /// `E0101`: an expression was required.
+    ExpectedExpression,
+    /// `E0102`: the left side of `=` is not a variable or property.
+    InvalidAssignmentTarget,
+    /// `E0103`: a function declares more than 255 parameters.
+    TooManyParameters,
+    /// `E0104`: a call passes more than 255 arguments.
+    TooManyArguments,
+
+    // E02xx: resolving.
+    /// `E0200`: a local variable is read in its own initializer.
+    ReadInOwnInitializer,
+    /// `E0201`: `super` outside of any class.
+    SuperOutsideClass,
+    /// `E0202`: `super` in a class that has no superclass.
+    SuperWithoutSuperclass,
+    /// `E0203`: `this` outside of any class.
+    ThisOutsideClass,
+    /// `E0204`: `return` in top-level code.
+    ReturnFromTopLevel,
+    /// `E0205`: `return` with a value inside `init`.
+    ReturnValueFromInitializer,
+    /// `E0206`: a class names itself as its superclass.
+    InheritFromSelf,
+    /// `E0207`: a local scope declares the same name twice.
+    AlreadyDeclared,
+
+    // E03xx: compiling to bytecode.
+    /// `E0300`: a function captures more than 256 variables.
+    TooManyUpvalues,
+    /// `E0301`: a function has more than 256 locals in scope.
+    TooManyLocals,
+    /// `E0302`: a function needs more than 65536 constants.
+    TooManyConstants,
+    /// `E0303`: a branch jumps over more than 65535 bytes of code.
+    JumpTooLarge,
+    /// `E0304`: a loop body is longer than 65535 bytes of code.
+    LoopTooLarge,
+
+    // E04xx: running.
+    /// `E0400`: unary `-` applied to a non-number.
+    OperandNotNumber,
+    /// `E0401`: an arithmetic or comparison operand is not a number.
+    OperandsNotNumbers,
+    /// `E0402`: `+` applied to anything but two numbers or two strings.
+    OperandsNotNumbersOrStrings,
+    /// `E0403`: `!` applied to a non-boolean.
+    OperandNotBoolean,
+    /// `E0404`: division by zero.
+    DivideByZero,
+    /// `E0405`: a variable that was never defined.
+    UndefinedVariable,
+    /// `E0406`: a property that is neither a field nor a method.
+    UndefinedProperty,
+    /// `E0407`: calling a value that is not a function or class.
+    NotCallable,
+    /// `E0408`: a call with the wrong number of arguments.
+    WrongArity,
+    /// `E0409`: reading or writing a property on a non-instance.
+    NotAnInstance,
+    /// `E0410`: inheriting from a value that is not a class.
+    SuperclassNotClass,
+    /// `E0411`: calls nested too deeply.
+    StackOverflow,
+    /// `E0412`: the VM met an instruction it doesn't know, or one whose
+    /// operands have the wrong kind of value.
+    InvalidBytecode,
+    /// `E0413`: a native function or userdata hook provided by the host
+    /// returned an error.
+    NativeError,
+}
+
+impl ErrorCode {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ErrorCode::UnexpectedCharacter => "E0001",
+            ErrorCode::UnterminatedString => "E0002",
+            ErrorCode::ExpectedToken => "E0100",
+            ErrorCode::ExpectedExpression => "E0101",
+            ErrorCode::InvalidAssignmentTarget => "E0102",
+            ErrorCode::TooManyParameters => "E0103",
+            ErrorCode::TooManyArguments => "E0104",
+            ErrorCode::ReadInOwnInitializer => "E0200",
+            ErrorCode::SuperOutsideClass => "E0201",
+            ErrorCode::SuperWithoutSuperclass => "E0202",
+            ErrorCode::ThisOutsideClass => "E0203",
+            ErrorCode::ReturnFromTopLevel => "E0204",
+            ErrorCode::ReturnValueFromInitializer => "E0205",
+            ErrorCode::InheritFromSelf => "E0206",
+            ErrorCode::AlreadyDeclared => "E0207",
+            ErrorCode::TooManyUpvalues => "E0300",
+            ErrorCode::TooManyLocals => "E0301",
+            ErrorCode::TooManyConstants => "E0302",
+            ErrorCode::JumpTooLarge => "E0303",
+            ErrorCode::LoopTooLarge => "E0304",
+            ErrorCode::OperandNotNumber => "E0400",
+            ErrorCode::OperandsNotNumbers => "E0401",
+            ErrorCode::OperandsNotNumbersOrStrings => "E0402",
+            ErrorCode::OperandNotBoolean => "E0403",
+            ErrorCode::DivideByZero => "E0404",
+            ErrorCode::UndefinedVariable => "E0405",
+            ErrorCode::UndefinedProperty => "E0406",
+            ErrorCode::NotCallable => "E0407",
+            ErrorCode::WrongArity => "E0408",
+            ErrorCode::NotAnInstance => "E0409",
+            ErrorCode::SuperclassNotClass => "E0410",
+            ErrorCode::StackOverflow => "E0411",
+            ErrorCode::InvalidBytecode => "E0412",
+            ErrorCode::NativeError => "E0413",
+        }
+    }
+}
+
+/// Spans covering more lines than this only show their first and last few.
+const MAX_SNIPPET_LINES: usize = 6;
+
+/// An error ready to be shown to the user: the message, where it happened,
+/// and optionally a label on the underlined span and a help note.
+#[derive(Debug)]
+pub struct Diagnostic {
+    code: ErrorCode,
     message: String,
+    line: usize,
+    span: Option<Span>,
+    label: Option<String>,
+    help: Option<String>,
+    trace: Vec<TraceFrame>,
 }
 
+impl Diagnostic {
+    /// A diagnostic known only by line, as the bytecode backend reports them.
+    pub fn new(code: ErrorCode, message: impl Into<String>, line: usize) -> Self {
+        Diagnostic {
+            code,
+            message: message.into(),
+            line,
+            span: None,
+            label: None,
+            help: None,
+            trace: Vec::new(),
+        }
+    }
+
+    pub fn at(code: ErrorCode, message: impl Into<String>, span: Span) -> Self {
+        Diagnostic { span: Some(span), ..Diagnostic::new(code, message, span.start.line) }
+    }
+
+    pub fn with_label(mut self, label: impl Into<String>) -> Self {
+        self.label = Some(label.into());
+        self
+    }
+
+    pub fn with_help(mut self, help: impl Into<String>) -> Self {
+        self.help = Some(help.into());
+        self
+    }
+
+    pub fn with_trace(mut self, trace: Vec<TraceFrame>) -> Self {
+        self.trace = trace;
+        self
+    }
+
+    /// Writes to stderr in the reporter's format; human-readable output is
+    /// coloured when stderr is a terminal.
+    pub fn report(&self, reporter: &Reporter) {
+        let source = &reporter.source;
+        match reporter.format {
+            ErrorFormat::Human => {
+                let colour = std::io::stderr().is_terminal() && std::env::var_os("NO_COLOR").is_none();
+                eprint!("{}", self.render(source, colour));
+            }
+            ErrorFormat::Json => eprintln!("{}", self.to_json(source)),
+        }
+    }
+
+    fn to_json(&self, source: &Source) -> String {
+        let lines: Vec<&str> = source.text.as_deref().map_or_else(Vec::new, |text| text.lines().collect());
+        let file = &source.name;
+        let optional = |text: &Option<String>| text.as_deref().map_or_else(|| String::from("null"), json_string);
+
+        let (start, end) = match self.span.map(|span| Diagnostic::clamp(span, &lines)) {
+            Some(span) => (
+                format!("{{\"line\":{},\"column\":{}}}", span.start.line, span.start.column),
+                format!("{{\"line\":{},\"column\":{}}}", span.end.line, span.end.column),
+            ),
+            None => {
+                let position = format!("{{\"line\":{},\"column\":null}}", self.line);
+                (position.clone(), position)
+            }
+        };
+
+        let trace: Vec<String> = self
+            .trace
+            .iter()
+            .map(|frame| {
+                format!(
+                    "{{\"function\":{},\"file\":{},\"line\":{}}}",
+                    json_string(&frame.function),
+                    json_string(file),
+                    frame.line
+                )
+            })
+            .collect();
+
+        format!(
+            "{{\"code\":{},\"severity\":\"error\",\"message\":{},\"file\":{},\"start\":{start},\"end\":{end},\"label\":{},\"help\":{},\"trace\":[{}]}}",
+            json_string(self.code.as_str()),
+            json_string(&self.message),
+            json_string(file),
+            optional(&self.label),
+            optional(&self.help),
+            trace.join(","),
+        )
+    }
+
+    fn render(&self, source: &Source, colour: bool) -> String {
+        let paint = |code: &str, text: &str| {
+            if colour {
+                format!("\x1b[{code}m{text}\x1b[0m")
+            } else {
+                text.to_string()
+            }
+        };
+        const RED: &str = "1;31";
+        const BLUE: &str = "1;34";
+
+        let lines: Vec<&str> = source.text.as_deref().map_or_else(Vec::new, |text| text.lines().collect());
+        let span = self.span.map(|span| Diagnostic::clamp(span, &lines));
+        let snippet = self.snippet(span, &lines);
+
+        let name = &source.name;
+        let location = match span {
+            Some(span) => format!("{name}:{}:{}", span.start.line, span.start.column),
+            None => format!("{name}:{}", self.line),
+        };
+
+        let width = snippet.last().map_or(0, |(number, _, _)| number.to_string().len());
+        let gutter = paint(BLUE, &format!("{:width$} |", ""));
+
+        let mut out = format!("{}{}\n", paint(RED, "error"), paint("1", &format!(": {}", self.message)));
+        out.push_str(&format!("{:width$}{} {location}\n", "", paint(BLUE, "-->")));
+
+        if !snippet.is_empty() {
+            out.push_str(&format!("{gutter}\n"));
+        }
+
+        let mut previous = None;
+        for (index, (number, text, underline)) in snippet.iter().enumerate() {
+            if previous.is_some_and(|previous| number - previous > 1) {
+                out.push_str(&format!("{}\n", paint(BLUE, "...")));
+            }
+            previous = Some(*number);
+
+            out.push_str(&format!("{} {text}\n", paint(BLUE, &format!("{number:>width$} |"))));
+
+            let Some((start, end)) = *underline else { continue };
+            // Mirror tabs so the carets line up however the terminal renders them.
+            let indent: String = text.chars().take(start - 1).map(|c| if c == '\t' { '\t' } else { ' ' }).collect();
+            let carets = "^".repeat((end - start).max(1));
+            let label = match &self.label {
+                Some(label) if index == snippet.len() - 1 => format!(" {label}"),
+                _ => String::new(),
+            };
+            out.push_str(&format!("{gutter} {indent}{}\n", paint(RED, &format!("{carets}{label}"))));
+        }
+
+        if let Some(help) = &self.help {
+            if !snippet.is_empty() {
+                out.push_str(&format!("{gutter}\n"));
+            }
+            out.push_str(&format!("{:width$} {} help: {help}\n", "", paint(BLUE, "=")));
+        }
+
+        // An error in top-level code has nothing to add beyond the location.
+        if self.trace.len() > 1 {
+            out.push_str(&format!("{}\n", paint("1", "stack trace, most recent call first:")));
+            for row in Diagnostic::fold_trace(&self.trace, name) {
+                out.push_str(&format!("  {row}\n"));
+            }
+        }
+
+        out
+    }
+
+    /// One row per frame, except that a long run of identical frames, as
+    /// deep recursion produces, is cut short with a count of the rest.
+    fn fold_trace(trace: &[TraceFrame], name: &str) -> Vec<String> {
+        let mut rows = Vec::new();
+        let mut index = 0;
+        while index < trace.len() {
+            let frame = &trace[index];
+            let run = trace[index..].iter().take_while(|other| *other == frame).count();
+            for _ in 0..run.min(MAX_REPEATED_FRAMES) {
+                rows.push(format!("at {} ({name}:{})", frame.function, frame.line));
+            }
+            if run > MAX_REPEATED_FRAMES {
+                let more = run - MAX_REPEATED_FRAMES;
+                let plural = if more == 1 { "" } else { "s" };
+                rows.push(format!("[previous frame repeated {more} more time{plural}]"));
+            }
+            index += run;
+        }
+        rows
+    }
+
+    /// The source lines to quote, each with its number and the 1-based
+    /// column range to underline on it.
+    #[allow(clippy::type_complexity)]
+    fn snippet<'a>(&self, span: Option<Span>, lines: &[&'a str]) -> Vec<(usize, &'a str, Option<(usize, usize)>)> {
+        let Some(Span { start, end }) = span else {
+            return match lines.get(self.line.wrapping_sub(1)) {
+                Some(text) => vec![(self.line, *text, None)],
+                None => Vec::new(),
+            };
+        };
+        let last = end.line.min(lines.len());
+        if start.line == 0 || start.line > last {
+            return Vec::new();
+        }
+
+        let mut snippet = Vec::new();
+        for number in start.line..=last {
+            let text = lines[number - 1];
+            let length = text.chars().count();
+            let from = if number == start.line {
+                start.column
+            } else {
+                text.chars().take_while(|c| c.is_whitespace()).count() + 1
+            };
+            let to = if number == end.line { end.column } else { length + 1 };
+            snippet.push((number, text, Some((from, to.max(from)))));
+        }
+
+        if snippet.len() > MAX_SNIPPET_LINES {
+            snippet.drain(MAX_SNIPPET_LINES / 2..snippet.len() - MAX_SNIPPET_LINES / 2);
+        }
+        snippet
+    }
+
+    /// The end of input can sit on the empty line after a trailing newline;
+    /// such spans are moved to just past the end of the last real line.
+    fn clamp(span: Span, lines: &[&str]) -> Span {
+        let Some(last) = lines.last() else { return span };
+        if span.start.line <= lines.len() {
+            return span;
+        }
+
+        let start = Location { line: lines.len(), column: last.chars().count() + 1, ..span.start };
+        Span { start, end: start }
+    }
+}
+
+fn json_string(text: &str) -> String {
+    let mut out = String::with_capacity(text.len() + 2);
+    out.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if c.is_control() => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// Returned by either backend once a runtime error has been reported.
+pub struct LoxRuntimeError;
+
+pub struct Lox;
+
 impl Lox {
-    pub fn error(line: usize, message: String) -> Lox {
-        Lox { line, message }
+    pub fn error(code: ErrorCode, line: usize, message: String) -> Diagnostic {
+        Diagnostic::new(code, message, line)
+    }
+
+    pub fn parse_error(code: ErrorCode, token: &Token, message: String) -> Diagnostic {
+        let label = if token.ttype == TokenType::Eof {
+            String::from("found end of file")
+        } else {
+            format!("found '{}'", token.lexeme)
+        };
+        Diagnostic::at(code, message, token.span).with_label(label)
+    }
+
+    pub fn resolve_error(code: ErrorCode, token: &Token, message: String) -> Diagnostic {
+        Diagnostic::at(code, message, token.span)
     }
 
-    pub fn report(&self, loc: &str) {
-        eprintln!(
-            "[line {}] Error chars [{loc}] : {}",
-            self.line, self.message
+    pub fn runtime_error(code: ErrorCode, token: &Token, message: &str) -> Diagnostic {
+        Diagnostic::at(code, message, token.span)
+    }
+
+    pub fn runtime_error_at(code: ErrorCode, line: usize, message: &str) -> Diagnostic {
+        Diagnostic::new(code, message, line)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn source(text: &str) -> Source {
+        Source { name: String::from("test.lox"), text: Some(text.to_string()) }
+    }
+
+    fn span(start: (usize, usize, usize), end: (usize, usize, usize)) -> Span {
+        let location = |(offset, line, column)| Location { offset, line, column };
+        Span { start: location(start), end: location(end) }
+    }
+
+    #[test]
+    fn underlines_the_span_with_its_label_and_help() {
+        let diagnostic = Diagnostic::at(ErrorCode::OperandsNotNumbers, "Operands must be two numbers.", span((14, 2, 9), (15, 2, 10)))
+            .with_label("this operator")
+            .with_help("convert both sides first");
+
+        assert_eq!(
+            diagnostic.render(&source("var a = 1;\nprint a - \"b\";\n"), false),
+            "error: Operands must be two numbers.\n \
+             --> test.lox:2:9\n  \
+             |\n\
+             2 | print a - \"b\";\n  \
+             |         ^ this operator\n  \
+             |\n  \
+             = help: convert both sides first\n"
         );
     }
 
-    pub fn parse_error(token: &Token, message: String) {
-        if token.ttype == TokenType::Eof {
-            Lox::report(&Lox { line: token.line, message }, "at end.");
-        } else {
-            Lox::report(&Lox { line: token.line, message }, &format!(" at '{}'", token.lexeme));
-        }
+    #[test]
+    fn underlines_every_line_of_a_multi_line_span() {
+        let text = "print (1 +\n    nil);";
+        let diagnostic = Diagnostic::at(ErrorCode::OperandsNotNumbers, "Operands must be two numbers.", span((6, 1, 7), (20, 2, 9)));
+
+        assert_eq!(
+            diagnostic.render(&source(text), false),
+            "error: Operands must be two numbers.\n \
+             --> test.lox:1:7\n  \
+             |\n\
+             1 | print (1 +\n  \
+             |       ^^^^\n\
+             2 |     nil);\n  \
+             |     ^^^^\n"
+        );
     }
 
-    pub fn runtime_error(token: &Token, message: &'static str) {
-        eprintln!("{}\n[line {}]", message, token.line)
+    #[test]
+    fn points_past_the_last_line_at_end_of_input() {
+        let diagnostic = Diagnostic::at(ErrorCode::ExpectedToken, "Expect ';' after value.", span((8, 2, 1), (8, 2, 1)))
+            .with_label("found end of file");
+
+        assert_eq!(
+            diagnostic.render(&source("print 1\n"), false),
+            "error: Expect ';' after value.\n \
+             --> test.lox:1:8\n  \
+             |\n\
+             1 | print 1\n  \
+             |        ^ found end of file\n"
+        );
+    }
+
+    #[test]
+    fn falls_back_to_the_line_without_source_or_span() {
+        let diagnostic = Diagnostic::new(ErrorCode::StackOverflow, "Stack overflow.", 3);
+
+        assert_eq!(diagnostic.render(&Reporter::default().source, false), "error: Stack overflow.\n--> <script>:3\n");
+        assert_eq!(
+            diagnostic.render(&source("a;\nb;\nfun f() { f(); }\n"), false),
+            "error: Stack overflow.\n \
+             --> test.lox:3\n  \
+             |\n\
+             3 | fun f() { f(); }\n"
+        );
+    }
+
+    #[test]
+    fn emits_one_json_object_with_code_and_positions() {
+        let diagnostic = Diagnostic::at(ErrorCode::UndefinedVariable, "Undefined variable 'x'.", span((6, 1, 7), (7, 1, 8)))
+            .with_help("declare it with 'var x' before using it");
+
+        assert_eq!(
+            diagnostic.to_json(&source("print x;\n")),
+            "{\"code\":\"E0405\",\"severity\":\"error\",\"message\":\"Undefined variable 'x'.\",\"file\":\"test.lox\",\
+             \"start\":{\"line\":1,\"column\":7},\"end\":{\"line\":1,\"column\":8},\
+             \"label\":null,\"help\":\"declare it with 'var x' before using it\",\"trace\":[]}"
+        );
+    }
+
+    #[test]
+    fn json_leaves_columns_null_without_a_span_and_escapes_text() {
+        let diagnostic = Diagnostic::new(ErrorCode::UnexpectedCharacter, "Unexpected \"\\\".\n", 2);
+
+        assert_eq!(
+            diagnostic.to_json(&Reporter::default().source),
+            "{\"code\":\"E0001\",\"severity\":\"error\",\"message\":\"Unexpected \\\"\\\\\\\".\\n\",\"file\":\"<script>\",\
+             \"start\":{\"line\":2,\"column\":null},\"end\":{\"line\":2,\"column\":null},\
+             \"label\":null,\"help\":null,\"trace\":[]}"
+        );
+    }
+
+    #[test]
+    fn folds_only_runs_longer_than_the_limit() {
+        let frame = |function: &str, line| TraceFrame { function: function.to_string(), line };
+        let mut trace = vec![frame("f", 2); MAX_REPEATED_FRAMES];
+        trace.push(frame("script", 5));
+        assert_eq!(Diagnostic::fold_trace(&trace, "a.lox"), ["at f (a.lox:2)", "at f (a.lox:2)", "at f (a.lox:2)", "at script (a.lox:5)"]);
+
+        trace.insert(0, frame("f", 2));
+        assert_eq!(Diagnostic::fold_trace(&trace, "a.lox")[3], "[previous frame repeated 1 more time]");
     }
 }
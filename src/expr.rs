@@ -1,4 +1,6 @@
-use crate::token::{Object, Token};
+use std::cell::Cell;
+
+use crate::token::{Object, Span, Token};
 
 #[derive(Debug)]
 pub enum Expr {
@@ -6,6 +8,34 @@ pub enum Expr {
     Grouping(GroupingExpr),
     Unary(UnaryExpr),
     Literal(LiteralExpr),
+    Variable(VariableExpr),
+    Assign(AssignExpr),
+    Logical(LogicalExpr),
+    Call(CallExpr),
+    Get(GetExpr),
+    Set(SetExpr),
+    This(ThisExpr),
+    Super(SuperExpr),
+}
+
+impl Expr {
+    /// The source range the whole expression was parsed from.
+    pub fn span(&self) -> Span {
+        match self {
+            Expr::Binary(expr) => expr.left.span().to(expr.right.span()),
+            Expr::Grouping(expr) => expr.span,
+            Expr::Unary(expr) => expr.operator.span.to(expr.right.span()),
+            Expr::Literal(expr) => expr.span,
+            Expr::Variable(expr) => expr.name.span,
+            Expr::Assign(expr) => expr.name.span.to(expr.value.span()),
+            Expr::Logical(expr) => expr.left.span().to(expr.right.span()),
+            Expr::Call(expr) => expr.callee.span().to(expr.paren.span),
+            Expr::Get(expr) => expr.object.span().to(expr.name.span),
+            Expr::Set(expr) => expr.object.span().to(expr.value.span()),
+            Expr::This(expr) => expr.keyword.span,
+            Expr::Super(expr) => expr.keyword.span.to(expr.method.span),
+        }
+    }
 }
 
 pub trait Visitor<T> {
@@ -13,6 +43,14 @@ pub trait Visitor<T> {
     fn visit_grouping(&self, grouping: &GroupingExpr) -> T;
     fn visit_unary(&self, unary: &UnaryExpr) -> T;
     fn visit_literal(&self, literal: &LiteralExpr) -> T;
+    fn visit_variable(&self, variable: &VariableExpr) -> T;
+    fn visit_assign(&self, assign: &AssignExpr) -> T;
+    fn visit_logical(&self, logical: &LogicalExpr) -> T;
+    fn visit_call(&self, call: &CallExpr) -> T;
+    fn visit_get(&self, get: &GetExpr) -> T;
+    fn visit_set(&self, set: &SetExpr) -> T;
+    fn visit_this(&self, this: &ThisExpr) -> T;
+    fn visit_super(&self, super_expr: &SuperExpr) -> T;
 }
 
 macro_rules! define_ast {
@@ -34,7 +72,17 @@ macro_rules! define_ast {
     };
 }
 
+pub(crate) use define_ast;
+
 define_ast!(BinaryExpr, visit_binary : left: Box<Expr>, operator: Token, right: Box<Expr>
-    ;GroupingExpr, visit_grouping : expr: Box<Expr>
+    ;GroupingExpr, visit_grouping : expr: Box<Expr>, span: Span
     ;UnaryExpr, visit_unary : operator: Token, right: Box<Expr>
-    ;LiteralExpr, visit_literal : value: Object);
+    ;LiteralExpr, visit_literal : value: Object, span: Span
+    ;VariableExpr, visit_variable : name: Token, depth: Cell<Option<usize>>
+    ;AssignExpr, visit_assign : name: Token, value: Box<Expr>, depth: Cell<Option<usize>>
+    ;LogicalExpr, visit_logical : left: Box<Expr>, operator: Token, right: Box<Expr>
+    ;CallExpr, visit_call : callee: Box<Expr>, paren: Token, arguments: Vec<Expr>
+    ;GetExpr, visit_get : object: Box<Expr>, name: Token
+    ;SetExpr, visit_set : object: Box<Expr>, name: Token, value: Box<Expr>
+    ;ThisExpr, visit_this : keyword: Token, depth: Cell<Option<usize>>
+    ;SuperExpr, visit_super : keyword: Token, method: Token, depth: Cell<Option<usize>>);
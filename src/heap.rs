@@ -0,0 +1,396 @@
+use std::cell::RefCell;
+use std::marker::PhantomData;
+use std::ops::Deref;
+use std::rc::Rc;
+
+use crate::class::Fields;
+use crate::environment::Environment;
+use crate::token::Object;
+use crate::vm;
+
+/// Handle to an object owned by the `Heap`. Copying a handle does not keep
+/// the object alive: only being reachable from the interpreter's roots does.
+/// The generation tells a handle to a swept object apart from one to
+/// whatever reused its slot.
+pub struct Gc<T> {
+    index: usize,
+    generation: u32,
+    marker: PhantomData<T>,
+}
+
+impl<T> Clone for Gc<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for Gc<T> {}
+
+impl<T> PartialEq for Gc<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.index == other.index && self.generation == other.generation
+    }
+}
+
+impl<T> Eq for Gc<T> {}
+
+impl<T> std::fmt::Debug for Gc<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Gc({}.{})", self.index, self.generation)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct GcConfig {
+    /// Number of live objects that triggers the first collection.
+    pub initial_threshold: usize,
+    /// After a collection, the next one runs once the heap has grown to this
+    /// multiple of what survived.
+    pub growth_factor: usize,
+    /// Collect before every allocation, to flush out missing roots.
+    pub stress: bool,
+}
+
+impl Default for GcConfig {
+    fn default() -> Self {
+        GcConfig {
+            initial_threshold: 1024,
+            growth_factor: 2,
+            stress: false,
+        }
+    }
+}
+
+/// Gray set of the mark phase. `Trace` implementations report the handles
+/// they hold here; the heap then marks and traces those objects in turn.
+#[derive(Default)]
+pub struct Tracer {
+    environments: Vec<Gc<Environment>>,
+    fields: Vec<Gc<Fields>>,
+    upvalues: Vec<Gc<vm::Upvalue>>,
+    vm_fields: Vec<Gc<vm::Fields>>,
+}
+
+impl Tracer {
+    /// Keeps everything `value` refers to alive through this collection.
+    pub fn value(&mut self, value: &Object) {
+        value.trace(self);
+    }
+
+    pub(crate) fn mark<T: Managed>(&mut self, handle: Gc<T>) {
+        T::gray(self).push(handle);
+    }
+}
+
+pub trait Trace {
+    fn trace(&self, tracer: &mut Tracer);
+}
+
+/// Values the host holds on to between calls into the interpreter, each
+/// pinned by a `Rooted` until it is dropped. The collector treats them as
+/// roots.
+#[derive(Default)]
+pub struct Pins {
+    slots: Vec<Option<Object>>,
+    free: Vec<usize>,
+}
+
+impl Trace for Pins {
+    fn trace(&self, tracer: &mut Tracer) {
+        self.slots.iter().flatten().for_each(|value| value.trace(tracer));
+    }
+}
+
+/// A Lox value held by the host. The value stays alive, however many
+/// collections run, until the handle is dropped; copies taken out of it are
+/// only safe to use while it lives.
+pub struct Rooted {
+    value: Object,
+    slot: usize,
+    pins: Rc<RefCell<Pins>>,
+}
+
+impl Rooted {
+    pub fn new(pins: Rc<RefCell<Pins>>, value: Object) -> Self {
+        let slot = {
+            let mut table = pins.borrow_mut();
+            match table.free.pop() {
+                Some(slot) => {
+                    table.slots[slot] = Some(value.clone());
+                    slot
+                }
+                None => {
+                    table.slots.push(Some(value.clone()));
+                    table.slots.len() - 1
+                }
+            }
+        };
+        Rooted { value, slot, pins }
+    }
+}
+
+impl Deref for Rooted {
+    type Target = Object;
+
+    fn deref(&self) -> &Object {
+        &self.value
+    }
+}
+
+impl Clone for Rooted {
+    fn clone(&self) -> Self {
+        Rooted::new(Rc::clone(&self.pins), self.value.clone())
+    }
+}
+
+impl Drop for Rooted {
+    fn drop(&mut self) {
+        let mut table = self.pins.borrow_mut();
+        table.slots[self.slot] = None;
+        table.free.push(self.slot);
+    }
+}
+
+impl std::fmt::Debug for Rooted {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.value.fmt(f)
+    }
+}
+
+impl std::fmt::Display for Rooted {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.value.fmt(f)
+    }
+}
+
+/// Objects that live on the heap, each kind in its own arena and with its
+/// own list in the gray set.
+pub trait Managed: Trace + Sized {
+    fn arena(heap: &Heap) -> &Arena<Self>;
+    fn arena_mut(heap: &mut Heap) -> &mut Arena<Self>;
+    fn gray(tracer: &mut Tracer) -> &mut Vec<Gc<Self>>;
+}
+
+impl Managed for Environment {
+    fn arena(heap: &Heap) -> &Arena<Self> {
+        &heap.environments
+    }
+
+    fn arena_mut(heap: &mut Heap) -> &mut Arena<Self> {
+        &mut heap.environments
+    }
+
+    fn gray(tracer: &mut Tracer) -> &mut Vec<Gc<Self>> {
+        &mut tracer.environments
+    }
+}
+
+impl Managed for Fields {
+    fn arena(heap: &Heap) -> &Arena<Self> {
+        &heap.fields
+    }
+
+    fn arena_mut(heap: &mut Heap) -> &mut Arena<Self> {
+        &mut heap.fields
+    }
+
+    fn gray(tracer: &mut Tracer) -> &mut Vec<Gc<Self>> {
+        &mut tracer.fields
+    }
+}
+
+impl Managed for vm::Upvalue {
+    fn arena(heap: &Heap) -> &Arena<Self> {
+        &heap.upvalues
+    }
+
+    fn arena_mut(heap: &mut Heap) -> &mut Arena<Self> {
+        &mut heap.upvalues
+    }
+
+    fn gray(tracer: &mut Tracer) -> &mut Vec<Gc<Self>> {
+        &mut tracer.upvalues
+    }
+}
+
+impl Managed for vm::Fields {
+    fn arena(heap: &Heap) -> &Arena<Self> {
+        &heap.vm_fields
+    }
+
+    fn arena_mut(heap: &mut Heap) -> &mut Arena<Self> {
+        &mut heap.vm_fields
+    }
+
+    fn gray(tracer: &mut Tracer) -> &mut Vec<Gc<Self>> {
+        &mut tracer.vm_fields
+    }
+}
+
+pub struct Arena<T> {
+    slots: Vec<Option<T>>,
+    /// Bumped whenever a slot is swept, invalidating its old handles.
+    generations: Vec<u32>,
+    marked: Vec<bool>,
+    /// Indexes of swept slots, reused before the arena grows.
+    free: Vec<usize>,
+}
+
+impl<T> Default for Arena<T> {
+    fn default() -> Self {
+        Arena { slots: Vec::new(), generations: Vec::new(), marked: Vec::new(), free: Vec::new() }
+    }
+}
+
+impl<T> Arena<T> {
+    fn alloc(&mut self, value: T) -> Gc<T> {
+        let index = match self.free.pop() {
+            Some(index) => {
+                self.slots[index] = Some(value);
+                index
+            }
+            None => {
+                self.slots.push(Some(value));
+                self.generations.push(0);
+                self.marked.push(false);
+                self.slots.len() - 1
+            }
+        };
+        Gc { index, generation: self.generations[index], marker: PhantomData }
+    }
+
+    /// Panics on a handle whose object has been collected, even if its slot
+    /// now holds another one.
+    fn check(&self, handle: Gc<T>) -> usize {
+        assert_eq!(self.generations[handle.index], handle.generation, "Use of a collected object.");
+        handle.index
+    }
+
+    fn get(&self, handle: Gc<T>) -> &T {
+        let index = self.check(handle);
+        self.slots[index].as_ref().expect("Live generations hold an object.")
+    }
+
+    fn get_mut(&mut self, handle: Gc<T>) -> &mut T {
+        let index = self.check(handle);
+        self.slots[index].as_mut().expect("Live generations hold an object.")
+    }
+
+    /// Returns whether the object was unmarked, i.e. still needs tracing.
+    fn mark(&mut self, handle: Gc<T>) -> bool {
+        let index = self.check(handle);
+        !std::mem::replace(&mut self.marked[index], true)
+    }
+
+    fn sweep(&mut self) {
+        let slots = self.slots.iter_mut().zip(&mut self.generations).zip(&mut self.marked);
+        for (index, ((slot, generation), marked)) in slots.enumerate() {
+            if slot.is_some() && !*marked {
+                *slot = None;
+                *generation = generation.wrapping_add(1);
+                self.free.push(index);
+            }
+            *marked = false;
+        }
+    }
+
+    fn live(&self) -> usize {
+        self.slots.len() - self.free.len()
+    }
+}
+
+/// Owns the runtime objects that can form reference cycles. On the tree
+/// backend those are environments (through closures stored in them) and
+/// instance fields; on the VM they are upvalues and instance fields. Every
+/// other object is immutable once built and stays reference counted.
+pub struct Heap {
+    environments: Arena<Environment>,
+    fields: Arena<Fields>,
+    upvalues: Arena<vm::Upvalue>,
+    vm_fields: Arena<vm::Fields>,
+    config: GcConfig,
+    next_gc: usize,
+}
+
+impl Heap {
+    pub fn new(config: GcConfig) -> Self {
+        Heap {
+            environments: Arena::default(),
+            fields: Arena::default(),
+            upvalues: Arena::default(),
+            vm_fields: Arena::default(),
+            next_gc: config.initial_threshold,
+            config,
+        }
+    }
+
+    pub fn alloc<T: Managed>(&mut self, value: T) -> Gc<T> {
+        T::arena_mut(self).alloc(value)
+    }
+
+    pub fn get<T: Managed>(&self, handle: Gc<T>) -> &T {
+        T::arena(self).get(handle)
+    }
+
+    pub fn get_mut<T: Managed>(&mut self, handle: Gc<T>) -> &mut T {
+        T::arena_mut(self).get_mut(handle)
+    }
+
+    pub fn live(&self) -> usize {
+        self.environments.live() + self.fields.live() + self.upvalues.live() + self.vm_fields.live()
+    }
+
+    pub fn should_collect(&self) -> bool {
+        self.config.stress || self.live() >= self.next_gc
+    }
+
+    /// Marks everything reachable from the handles already in `tracer`, then
+    /// frees the rest.
+    pub fn collect(&mut self, mut tracer: Tracer) {
+        loop {
+            let traced = self.trace_gray::<Environment>(&mut tracer)
+                | self.trace_gray::<Fields>(&mut tracer)
+                | self.trace_gray::<vm::Upvalue>(&mut tracer)
+                | self.trace_gray::<vm::Fields>(&mut tracer);
+            if !traced {
+                break;
+            }
+        }
+
+        self.environments.sweep();
+        self.fields.sweep();
+        self.upvalues.sweep();
+        self.vm_fields.sweep();
+        self.next_gc = (self.live() * self.config.growth_factor).max(self.config.initial_threshold);
+    }
+
+    /// Marks and traces the gray objects of one kind. Returns whether there
+    /// were any, since tracing them may have grayed objects of other kinds.
+    fn trace_gray<T: Managed>(&mut self, tracer: &mut Tracer) -> bool {
+        let mut traced = false;
+        while let Some(handle) = T::gray(tracer).pop() {
+            if T::arena_mut(self).mark(handle) {
+                T::arena(self).get(handle).trace(tracer);
+            }
+            traced = true;
+        }
+        traced
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[should_panic(expected = "Use of a collected object.")]
+    fn stale_handles_are_caught_after_their_slot_is_reused() {
+        let mut heap = Heap::new(GcConfig::default());
+        let stale = heap.alloc(Environment::new());
+        heap.collect(Tracer::default());
+
+        let fresh = heap.alloc(Environment::new());
+        assert_ne!(stale, fresh);
+        heap.get(stale);
+    }
+}
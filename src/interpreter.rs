@@ -1,89 +1,190 @@
-use crate::error::Lox;
-use crate::expr::{self, Expr, Visitor};
+use std::cell::{Cell, Ref, RefCell, RefMut};
+use std::collections::HashMap;
+use std::rc::Rc;
+
+
+use crate::callable::{Arity, LoxCallable, LoxFunction};
+use crate::class::LoxClass;
+use crate::environment::Environment;
+use crate::error::{ErrorCode, Lox, LoxRuntimeError, Reporter, TraceFrame};
+use crate::heap::{Gc, GcConfig, Heap, Managed, Pins, Rooted, Trace, Tracer};
+use crate::expr::{self, Expr};
+use crate::stmt::{self, Stmt};
+use crate::symbol::Symbol;
 use crate::token::{Object, Token};
 use crate::token_type::TokenType;
+use crate::vm::FRAMES_MAX;
+
+pub struct Interpreter {
+    heap: RefCell<Heap>,
+    globals: Gc<Environment>,
+    environment: Cell<Gc<Environment>>,
+    /// Environments of the blocks and calls the current one is nested in;
+    /// execution returns to them, so they are roots like `environment`.
+    frames: RefCell<Vec<Gc<Environment>>>,
+    /// Values that Rust code holds on to while evaluating further
+    /// subexpressions, which may allocate and so collect.
+    temporaries: RefCell<Vec<Object>>,
+    /// Calls currently running, so runaway recursion stops with an error
+    /// before it exhausts the Rust stack.
+    calls: Cell<usize>,
+    /// Values the host holds through `Rooted` handles.
+    pins: Rc<RefCell<Pins>>,
+}
+
+/// Keeps the values pushed onto it rooted until it is dropped.
+struct Roots<'a> {
+    interpreter: &'a Interpreter,
+    mark: usize,
+}
 
+impl Roots<'_> {
+    fn push(&self, value: &Object) {
+        self.interpreter.temporaries.borrow_mut().push(value.clone());
+    }
+}
 
-pub struct Interpreter;
+impl Drop for Roots<'_> {
+    fn drop(&mut self) {
+        self.interpreter.temporaries.borrow_mut().truncate(self.mark);
+    }
+}
 
-struct RuntimeError {
-    message: &'static str,
-    token: Token,
+pub struct RuntimeError {
+    code: ErrorCode,
+    message: String,
+    token: Box<Token>,
+    help: Option<String>,
+    /// Calls the error has unwound out of so far, innermost first.
+    trace: Vec<TraceFrame>,
+    /// Line reached in the call currently being unwound.
+    line: usize,
 }
 
 impl RuntimeError {
-    fn new(message: &'static str, token: Token) -> Self {
+    pub fn new(code: ErrorCode, message: String, token: Token) -> Self {
         RuntimeError {
+            code,
             message,
-            token
+            line: token.line,
+            token: Box::new(token),
+            help: None,
+            trace: Vec::new(),
+        }
+    }
+
+    pub fn with_help(mut self, help: String) -> Self {
+        self.help = Some(help);
+        self
+    }
+
+    /// Records the frame of `function` as the error unwinds out of it.
+    pub fn unwind_from(mut self, function: &Symbol) -> Self {
+        self.trace.push(TraceFrame { function: function.to_string(), line: self.line });
+        self
+    }
+
+    /// Moves the error to the caller's frame, at the line of the call.
+    fn return_to(mut self, line: usize) -> Self {
+        self.line = line;
+        self
+    }
+
+    fn report(mut self, reporter: &Reporter) {
+        self.trace.push(TraceFrame { function: String::from("script"), line: self.line });
+        let diagnostic = Lox::runtime_error(self.code, &self.token, &self.message).with_trace(self.trace);
+        match self.help {
+            Some(help) => diagnostic.with_help(help).report(reporter),
+            None => diagnostic.report(reporter),
         }
     }
 }
 
-impl Visitor<Result<Object, RuntimeError>> for Interpreter {
+/// Non-local exits out of statement execution: a runtime error, or a `return`
+/// carrying its value back to the enclosing call.
+pub enum Unwind {
+    Error(RuntimeError),
+    Return(Object),
+}
+
+impl From<RuntimeError> for Unwind {
+    fn from(error: RuntimeError) -> Self {
+        Unwind::Error(error)
+    }
+}
+
+impl expr::Visitor<Result<Object, RuntimeError>> for Interpreter {
     fn visit_binary(&self, binary: &expr::BinaryExpr) -> Result<Object, RuntimeError> {
         let left = self.evaluate(&binary.left)?;
-        let right = self .evaluate(&binary.right)?;
+        let roots = self.roots();
+        roots.push(&left);
+        let right = self.evaluate(&binary.right)?;
         let op = binary.operator.clone();
 
         match binary.operator.ttype {
             TokenType::Minus => {
                 match (left, right) {
                     (Object::Num(a), Object::Num(b)) => Ok(Object::Num(a - b)),
-                    _ => Err(RuntimeError::new("Operands must be two numbers.", op)),
+                    _ => Err(RuntimeError::new(ErrorCode::OperandsNotNumbers, String::from("Operands must be two numbers."), op)),
                 }
             }
             TokenType::Slash => {
                 match (left, right) {
                     (Object::Num(a), Object::Num(b)) => {
                         if b == 0.0 {
-                            return Err(RuntimeError::new("Cannot divide by zero.", op))
+                            return Err(RuntimeError::new(ErrorCode::DivideByZero, String::from("Cannot divide by zero."), op))
                         }
                         Ok(Object::Num(a / b))
                     }
-                    _ => Err(RuntimeError::new("Operands must be two numbers.", op)),
+                    _ => Err(RuntimeError::new(ErrorCode::OperandsNotNumbers, String::from("Operands must be two numbers."), op)),
                 }
             }
             TokenType::Star => {
                 match (left, right) {
                     (Object::Num(a), Object::Num(b)) => Ok(Object::Num(a * b)),
-                    _ => Err(RuntimeError::new("Operands must be two numbers.", op)),
+                    _ => Err(RuntimeError::new(ErrorCode::OperandsNotNumbers, String::from("Operands must be two numbers."), op)),
                 }
             }
             TokenType::Plus => {
                 match (left, right) {
                     (Object::Num(a), Object::Num(b)) => Ok(Object::Num(a + b)),
-                    (Object::Str(a), Object::Str(b)) => Ok(Object::Str(format!("{a}{b}"))),
-                    _ => Err(RuntimeError::new("Operands must be two numbers or two strings.", op)),
+                    (Object::Str(a), Object::Str(b)) => Ok(Object::Str(Symbol::intern(&format!("{a}{b}")))),
+                    _ => Err(RuntimeError::new(ErrorCode::OperandsNotNumbersOrStrings, String::from("Operands must be two numbers or two strings."), op)),
                 }
             }
             TokenType::Greater => {
                 match (left, right) {
                     (Object::Num(a), Object::Num(b)) => Ok(Object::Boolean(a > b)),
-                    _ => Err(RuntimeError::new("Operands must be two numbers.", op)),
+                    _ => Err(RuntimeError::new(ErrorCode::OperandsNotNumbers, String::from("Operands must be two numbers."), op)),
                 }
             }
             TokenType::GreaterEqual => {
                 match (left, right) {
                     (Object::Num(a), Object::Num(b)) => Ok(Object::Boolean(a >= b)),
-                    _ => Err(RuntimeError::new("Operands must be two numbers.", op)),
+                    _ => Err(RuntimeError::new(ErrorCode::OperandsNotNumbers, String::from("Operands must be two numbers."), op)),
                 }
             }
             TokenType::Less => {
                 match (left, right) {
                     (Object::Num(a), Object::Num(b)) => Ok(Object::Boolean(a < b)),
-                    _ => Err(RuntimeError::new("Operands must be two numbers.", op)),
+                    _ => Err(RuntimeError::new(ErrorCode::OperandsNotNumbers, String::from("Operands must be two numbers."), op)),
                 }
             }
             TokenType::LessEqual => {
                 match (left, right) {
                     (Object::Num(a), Object::Num(b)) => Ok(Object::Boolean(a <= b)),
-                    _ => Err(RuntimeError::new("Operands must be two numbers.", op)),
+                    _ => Err(RuntimeError::new(ErrorCode::OperandsNotNumbers, String::from("Operands must be two numbers."), op)),
+                }
+            }
+            TokenType::Exponent => {
+                match (left, right) {
+                    (Object::Num(a), Object::Num(b)) => Ok(Object::Num(a.powf(b))),
+                    _ => Err(RuntimeError::new(ErrorCode::OperandsNotNumbers, String::from("Operands must be two numbers."), op)),
                 }
             }
             TokenType::Equals => Ok(Object::Boolean(Self::is_equal(left, right))),
             TokenType::BangEqual => Ok(Object::Boolean(!Self::is_equal(left, right))),
-            _ => todo!(),
+            _ => unreachable!(),
         }
     }
 
@@ -99,13 +200,13 @@ impl Visitor<Result<Object, RuntimeError>> for Interpreter {
             TokenType::Minus => {
                 match right {
                     Object::Num(num) => Ok(Object::Num(-num)),
-                    _ => Err(RuntimeError::new("Operand must be a number.", op)),
+                    _ => Err(RuntimeError::new(ErrorCode::OperandNotNumber, String::from("Operand must be a number."), op)),
                 }
             }
             TokenType::Bang => {
                 match right {
                     Object::Boolean(b) => Ok(Object::Boolean(!b)),
-                    _ => Err(RuntimeError::new("Operand must be a boolean.", op)),
+                    _ => Err(RuntimeError::new(ErrorCode::OperandNotBoolean, String::from("Operand must be a boolean."), op)),
                 }
             }
             _ => unreachable!(),
@@ -115,14 +216,351 @@ impl Visitor<Result<Object, RuntimeError>> for Interpreter {
     fn visit_literal(&self, literal: &expr::LiteralExpr) -> Result<Object, RuntimeError> {
         Ok(literal.value.clone())
     }
+
+    fn visit_variable(&self, variable: &expr::VariableExpr) -> Result<Object, RuntimeError> {
+        self.look_up_variable(&variable.name, variable.depth.get())
+    }
+
+    fn visit_logical(&self, logical: &expr::LogicalExpr) -> Result<Object, RuntimeError> {
+        let left = self.evaluate(&logical.left)?;
+
+        // Short-circuit, yielding the deciding operand itself rather than a boolean.
+        match logical.operator.ttype {
+            TokenType::Or if Self::is_truthy(&left) => Ok(left),
+            TokenType::And if !Self::is_truthy(&left) => Ok(left),
+            _ => self.evaluate(&logical.right),
+        }
+    }
+
+    fn visit_call(&self, call: &expr::CallExpr) -> Result<Object, RuntimeError> {
+        let roots = self.roots();
+        let callee = self.evaluate(&call.callee)?;
+        roots.push(&callee);
+
+        let mut arguments = Vec::with_capacity(call.arguments.len());
+        for argument in &call.arguments {
+            let value = self.evaluate(argument)?;
+            roots.push(&value);
+            arguments.push(value);
+        }
+
+        let function: &dyn LoxCallable = match &callee {
+            Object::Function(function) => function.as_ref(),
+            Object::Native(native) => native.as_ref(),
+            Object::Class(class) => class,
+            _ => return Err(RuntimeError::new(ErrorCode::NotCallable, String::from("Can only call functions and classes."), call.paren.clone())),
+        };
+
+        if let Arity::Fixed(arity) = function.arity() {
+            if arguments.len() != arity {
+                return Err(RuntimeError::new(
+                    ErrorCode::WrongArity,
+                    format!("Expected {arity} arguments but got {}.", arguments.len()),
+                    call.paren.clone(),
+                ));
+            }
+        }
+
+        // Top-level code takes a frame too, as on the VM.
+        if self.calls.get() + 1 == FRAMES_MAX {
+            return Err(RuntimeError::new(ErrorCode::StackOverflow, String::from("Stack overflow."), call.paren.clone()));
+        }
+
+        self.calls.set(self.calls.get() + 1);
+        let result = function.call(self, &call.paren, arguments);
+        self.calls.set(self.calls.get() - 1);
+        result.map_err(|error| error.return_to(call.paren.line))
+    }
+
+    fn visit_get(&self, get: &expr::GetExpr) -> Result<Object, RuntimeError> {
+        match self.evaluate(&get.object)? {
+            Object::Instance(instance) => instance.get(self, &get.name),
+            Object::UserData(data) => data.property(&get.name),
+            _ => Err(RuntimeError::new(ErrorCode::NotAnInstance, String::from("Only instances have properties."), get.name.clone())),
+        }
+    }
+
+    fn visit_set(&self, set: &expr::SetExpr) -> Result<Object, RuntimeError> {
+        let object = self.evaluate(&set.object)?;
+        if !matches!(object, Object::Instance(_) | Object::UserData(_)) {
+            return Err(RuntimeError::new(ErrorCode::NotAnInstance, String::from("Only instances have fields."), set.name.clone()));
+        }
+
+        let roots = self.roots();
+        roots.push(&object);
+        let value = self.evaluate(&set.value)?;
+        match object {
+            Object::Instance(instance) => instance.set(self, &set.name, value.clone()),
+            Object::UserData(data) => data.set_property(&set.name, value.clone())?,
+            _ => unreachable!("Checked above."),
+        }
+        Ok(value)
+    }
+
+    fn visit_this(&self, this: &expr::ThisExpr) -> Result<Object, RuntimeError> {
+        self.look_up_variable(&this.keyword, this.depth.get())
+    }
+
+    fn visit_super(&self, super_expr: &expr::SuperExpr) -> Result<Object, RuntimeError> {
+        let distance = super_expr
+            .depth
+            .get()
+            .expect("The resolver always binds 'super' to a local scope.");
+        let environment = self.environment.get();
+
+        let (superclass, object) = {
+            let heap = self.heap();
+            let Object::Class(superclass) = Environment::get_at(&heap, environment, distance, &super_expr.keyword)? else {
+                unreachable!("'super' is always bound to a class.")
+            };
+            // `this` lives in the scope just inside the one holding `super`.
+            let this = heap.get(Environment::ancestor(&heap, environment, distance - 1)).value(&Symbol::intern("this"));
+            (superclass, this.expect("resolver binds 'this'"))
+        };
+
+        match superclass.find_method(&super_expr.method.lexeme) {
+            Some(method) => Ok(Object::Function(Rc::new(method.bind(self, object)))),
+            None => Err(RuntimeError::new(
+                ErrorCode::UndefinedProperty,
+                format!("Undefined property '{}'.", super_expr.method.lexeme),
+                super_expr.method.clone(),
+            )),
+        }
+    }
+
+    fn visit_assign(&self, assign: &expr::AssignExpr) -> Result<Object, RuntimeError> {
+        let value = self.evaluate(&assign.value)?;
+        match assign.depth.get() {
+            Some(distance) => Environment::assign_at(&mut self.heap_mut(), self.environment.get(), distance, &assign.name, value.clone())?,
+            None => Environment::assign(&mut self.heap_mut(), self.globals, &assign.name, value.clone())?,
+        }
+        Ok(value)
+    }
+}
+
+impl stmt::Visitor<Result<(), Unwind>> for Interpreter {
+    fn visit_expression(&self, stmt: &stmt::ExpressionStmt) -> Result<(), Unwind> {
+        self.evaluate(&stmt.expr)?;
+        Ok(())
+    }
+
+    fn visit_print(&self, stmt: &stmt::PrintStmt) -> Result<(), Unwind> {
+        let value = self.evaluate(&stmt.expr)?;
+        println!("{value}");
+        Ok(())
+    }
+
+    fn visit_var(&self, stmt: &stmt::VarStmt) -> Result<(), Unwind> {
+        let value = match &stmt.initializer {
+            Some(initializer) => self.evaluate(initializer)?,
+            None => Object::Nil,
+        };
+
+        self.heap_mut().get_mut(self.environment.get()).define(stmt.name.lexeme.clone(), value);
+        Ok(())
+    }
+
+    fn visit_block(&self, stmt: &stmt::BlockStmt) -> Result<(), Unwind> {
+        let environment = Environment::with_enclosing(self.environment.get());
+        self.execute_block(&stmt.statements, self.allocate(environment))
+    }
+
+    fn visit_if(&self, stmt: &stmt::IfStmt) -> Result<(), Unwind> {
+        if Self::is_truthy(&self.evaluate(&stmt.condition)?) {
+            self.execute(&stmt.then_branch)?;
+        } else if let Some(else_branch) = &stmt.else_branch {
+            self.execute(else_branch)?;
+        }
+        Ok(())
+    }
+
+    fn visit_while(&self, stmt: &stmt::WhileStmt) -> Result<(), Unwind> {
+        while Self::is_truthy(&self.evaluate(&stmt.condition)?) {
+            self.execute(&stmt.body)?;
+        }
+        Ok(())
+    }
+
+    fn visit_function(&self, stmt: &stmt::FunctionStmt) -> Result<(), Unwind> {
+        let closure = self.environment.get();
+        let function = Object::Function(Rc::new(LoxFunction::new(stmt, closure, false)));
+        self.heap_mut().get_mut(closure).define(stmt.name.lexeme.clone(), function);
+        Ok(())
+    }
+
+    fn visit_return(&self, stmt: &stmt::ReturnStmt) -> Result<(), Unwind> {
+        let value = match &stmt.value {
+            Some(value) => self.evaluate(value)?,
+            None => Object::Nil,
+        };
+
+        Err(Unwind::Return(value))
+    }
+
+    fn visit_class(&self, stmt: &stmt::ClassStmt) -> Result<(), Unwind> {
+        let superclass = match &stmt.superclass {
+            Some(superclass) => match superclass.accept(self)? {
+                Object::Class(class) => Some(class),
+                _ => {
+                    return Err(Unwind::Error(RuntimeError::new(
+                        ErrorCode::SuperclassNotClass,
+                        String::from("Superclass must be a class."),
+                        superclass.name.clone(),
+                    )))
+                }
+            },
+            None => None,
+        };
+
+        let enclosing = self.environment.get();
+        self.heap_mut().get_mut(enclosing).define(stmt.name.lexeme.clone(), Object::Nil);
+
+        // Methods of a subclass close over an extra scope that binds `super`.
+        let environment = match &superclass {
+            Some(superclass) => {
+                let mut environment = Environment::with_enclosing(enclosing);
+                environment.define(Symbol::intern("super"), Object::Class(Rc::clone(superclass)));
+                self.allocate(environment)
+            }
+            None => enclosing,
+        };
+
+        let methods = stmt
+            .methods
+            .iter()
+            .map(|method| {
+                let is_initializer = method.name.lexeme == "init";
+                let function = LoxFunction::new(method, environment, is_initializer);
+                (method.name.lexeme.clone(), Rc::new(function))
+            })
+            .collect::<HashMap<_, _>>();
+
+        let class = LoxClass::new(stmt.name.lexeme.clone(), superclass, methods);
+        Environment::assign(&mut self.heap_mut(), enclosing, &stmt.name, Object::Class(Rc::new(class)))?;
+        Ok(())
+    }
 }
 
 impl Interpreter {
-    pub fn interpret(&self, expr: Expr) {
-        let result = Self::evaluate(&Interpreter, &expr);
+    pub fn with_gc_config(config: GcConfig) -> Self {
+        let mut heap = Heap::new(config);
+        let globals = heap.alloc(Environment::new());
+        Interpreter {
+            heap: RefCell::new(heap),
+            globals,
+            environment: Cell::new(globals),
+            frames: RefCell::new(Vec::new()),
+            temporaries: RefCell::new(Vec::new()),
+            calls: Cell::new(0),
+            pins: Rc::default(),
+        }
+    }
+
+    /// Runs the statements in order, stopping at the first runtime error
+    /// after reporting it through `reporter`. Globals defined so far stay
+    /// defined. When the last statement is an expression, its value is handed
+    /// back.
+    pub fn interpret(&self, statements: &[Stmt], reporter: &Reporter) -> Result<Object, LoxRuntimeError> {
+        let Some((last, rest)) = statements.split_last() else { return Ok(Object::Nil) };
+
+        let result = rest.iter().try_for_each(|statement| self.execute(statement)).and_then(|()| match last {
+            Stmt::Expression(stmt) => self.evaluate(&stmt.expr).map_err(Unwind::Error),
+            _ => self.execute(last).map(|()| Object::Nil),
+        });
+
         match result {
-            Ok(value) => println!("{}", value.to_string()),
-            Err(error) => Lox::runtime_error(&error.token, error.message)
+            Ok(value) => Ok(value),
+            Err(Unwind::Return(_)) => unreachable!("The resolver rejects top-level return."),
+            Err(Unwind::Error(error)) => {
+                error.report(reporter);
+                Err(LoxRuntimeError)
+            }
+        }
+    }
+
+    pub fn define_global(&self, name: Symbol, value: Object) {
+        self.heap_mut().get_mut(self.globals).define(name, value);
+    }
+
+    /// Keeps `value` alive for the host until the handle is dropped.
+    pub fn root(&self, value: Object) -> Rooted {
+        Rooted::new(Rc::clone(&self.pins), value)
+    }
+
+    pub fn global(&self, name: &Symbol) -> Option<Object> {
+        self.heap().get(self.globals).value(name)
+    }
+
+    fn execute(&self, stmt: &Stmt) -> Result<(), Unwind> {
+        match stmt {
+            Stmt::Expression(stmt) => stmt.accept(self),
+            Stmt::Print(stmt) => stmt.accept(self),
+            Stmt::Var(stmt) => stmt.accept(self),
+            Stmt::Block(stmt) => stmt.accept(self),
+            Stmt::If(stmt) => stmt.accept(self),
+            Stmt::While(stmt) => stmt.accept(self),
+            Stmt::Function(stmt) => stmt.accept(self),
+            Stmt::Return(stmt) => stmt.accept(self),
+            Stmt::Class(stmt) => stmt.accept(self),
+        }
+    }
+
+    pub fn execute_block(
+        &self,
+        statements: &[Stmt],
+        environment: Gc<Environment>,
+    ) -> Result<(), Unwind> {
+        let previous = self.environment.replace(environment);
+        self.frames.borrow_mut().push(previous);
+
+        // Restore the outer scope even when a runtime error unwinds out of the block.
+        let result = statements.iter().try_for_each(|statement| self.execute(statement));
+
+        self.frames.borrow_mut().pop();
+        self.environment.set(previous);
+        result
+    }
+
+    pub fn heap(&self) -> Ref<'_, Heap> {
+        self.heap.borrow()
+    }
+
+    pub fn heap_mut(&self) -> RefMut<'_, Heap> {
+        self.heap.borrow_mut()
+    }
+
+    /// Moves `value` onto the heap, collecting first if the heap is due.
+    /// `value` is not reachable from any root yet, so it is traced as one.
+    pub fn allocate<T: Managed>(&self, value: T) -> Gc<T> {
+        if self.heap().should_collect() {
+            self.collect_garbage(Some(&value));
+        }
+        self.heap_mut().alloc(value)
+    }
+
+    pub fn collect_garbage(&self, pending: Option<&dyn Trace>) {
+        let mut tracer = Tracer::default();
+        tracer.mark(self.globals);
+        tracer.mark(self.environment.get());
+        self.frames.borrow().iter().for_each(|&frame| tracer.mark(frame));
+        self.temporaries.borrow().iter().for_each(|value| value.trace(&mut tracer));
+        self.pins.borrow().trace(&mut tracer);
+        if let Some(pending) = pending {
+            pending.trace(&mut tracer);
+        }
+
+        self.heap_mut().collect(tracer);
+    }
+
+    fn roots(&self) -> Roots<'_> {
+        let mark = self.temporaries.borrow().len();
+        Roots { interpreter: self, mark }
+    }
+
+    fn look_up_variable(&self, name: &Token, depth: Option<usize>) -> Result<Object, RuntimeError> {
+        match depth {
+            Some(distance) => Environment::get_at(&self.heap(), self.environment.get(), distance, name),
+            None => Environment::get(&self.heap(), self.globals, name),
         }
     }
 
@@ -132,17 +570,289 @@ impl Interpreter {
             Expr::Grouping(expr) => expr.accept(self),
             Expr::Unary(expr) => expr.accept(self),
             Expr::Literal(expr) => expr.accept(self),
+            Expr::Variable(expr) => expr.accept(self),
+            Expr::Assign(expr) => expr.accept(self),
+            Expr::Logical(expr) => expr.accept(self),
+            Expr::Call(expr) => expr.accept(self),
+            Expr::Get(expr) => expr.accept(self),
+            Expr::Set(expr) => expr.accept(self),
+            Expr::This(expr) => expr.accept(self),
+            Expr::Super(expr) => expr.accept(self),
         }
     }
 
+    fn is_truthy(object: &Object) -> bool {
+        !matches!(object, Object::Nil | Object::Boolean(false))
+    }
+
     fn is_equal(a: Object, b: Object) -> bool {
         match (a, b) {
             (Object::Num(a), Object::Num(b)) => a == b,
             (Object::Str(a), Object::Str(b)) => a == b,
             (Object::Boolean(a), Object::Boolean(b)) => a == b,
             (Object::Nil, Object::Nil) => true,
+            (Object::Function(a), Object::Function(b)) => Rc::ptr_eq(&a, &b),
+            (Object::Native(a), Object::Native(b)) => Rc::ptr_eq(&a, &b),
+            (Object::UserData(a), Object::UserData(b)) => Rc::ptr_eq(&a, &b),
+            (Object::Class(a), Object::Class(b)) => Rc::ptr_eq(&a, &b),
+            (Object::Instance(a), Object::Instance(b)) => a.ptr_eq(&b),
             _ => false,
         }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::callable::NativeFunction;
+    use crate::parser::Parser;
+    use crate::resolver::Resolver;
+    use crate::scanner::Scanner;
+    use crate::token::Span;
+
+    fn run(source: &str) -> Interpreter {
+        run_with(source, GcConfig::default())
+    }
+
+    fn run_with(source: &str, config: GcConfig) -> Interpreter {
+        let tokens = Scanner::new(source.as_bytes(), &Reporter::default()).scan_tokens().expect("test program should scan");
+        let statements = Parser::new(tokens, &Reporter::default()).parse().expect("test program should parse");
+        assert!(Resolver::new(&Reporter::default()).resolve(&statements).is_ok(), "test program should resolve");
+        let interpreter = Interpreter::with_gc_config(config);
+        assert!(interpreter.interpret(&statements, &Reporter::default()).is_ok(), "test program should run");
+        interpreter
+    }
+
+    fn global(interpreter: &Interpreter, name: &str) -> String {
+        let token = Token::new(TokenType::Ident, Symbol::intern(name), None, 0, Span::default());
+        match Environment::get(&interpreter.heap(), interpreter.globals, &token) {
+            Ok(value) => value.to_string(),
+            Err(_) => panic!("global '{name}' is not defined"),
+        }
+    }
+
+    #[test]
+    fn counter_closure_keeps_its_own_state() {
+        let interpreter = run("
+            fun makeCounter() {
+                var i = 0;
+                fun count() {
+                    i = i + 1;
+                    return i;
+                }
+                return count;
+            }
+
+            var first = makeCounter();
+            var second = makeCounter();
+            var a = first();
+            var b = first();
+            var c = second();
+        ");
+
+        assert_eq!(global(&interpreter, "a"), "1");
+        assert_eq!(global(&interpreter, "b"), "2");
+        assert_eq!(global(&interpreter, "c"), "1");
+    }
+
+    #[test]
+    fn closures_share_captured_variables() {
+        let interpreter = run("
+            var get;
+            var set;
+            {
+                var shared = \"before\";
+                fun getter() { return shared; }
+                fun setter(value) { shared = value; }
+                get = getter;
+                set = setter;
+            }
+            set(\"after\");
+            var seen = get();
+        ");
+
+        assert_eq!(global(&interpreter, "seen"), "after");
+    }
+
+    #[test]
+    fn closures_bind_variables_at_declaration() {
+        let interpreter = run("
+            var a = \"global\";
+            var first;
+            var second;
+            {
+                fun show() { return a; }
+                first = show();
+                var a = \"block\";
+                second = show();
+            }
+        ");
+
+        assert_eq!(global(&interpreter, "first"), "global");
+        assert_eq!(global(&interpreter, "second"), "global");
+    }
+
+    #[test]
+    fn closures_in_a_loop_see_the_loop_variable() {
+        // The desugared `for` declares its variable once, so every closure
+        // created in the loop observes its final value.
+        let interpreter = run("
+            var first;
+            var second;
+            for (var i = 0; i < 2; i = i + 1) {
+                fun current() { return i; }
+                if (i == 0) first = current; else second = current;
+            }
+            var a = first();
+            var b = second();
+        ");
+
+        assert_eq!(global(&interpreter, "a"), "2");
+        assert_eq!(global(&interpreter, "b"), "2");
+    }
+
+    #[test]
+    fn closures_in_a_loop_capture_per_iteration_locals() {
+        let interpreter = run("
+            var first;
+            var second;
+            for (var i = 0; i < 2; i = i + 1) {
+                var j = i;
+                fun current() { return j; }
+                if (i == 0) first = current; else second = current;
+            }
+            var a = first();
+            var b = second();
+        ");
+
+        assert_eq!(global(&interpreter, "a"), "0");
+        assert_eq!(global(&interpreter, "b"), "1");
+    }
+
+    #[test]
+    fn classes_bind_this_and_run_initializers() {
+        let interpreter = run("
+            class Point {
+                init(x, y) {
+                    this.x = x;
+                    this.y = y;
+                }
+                sum() { return this.x + this.y; }
+            }
+
+            var point = Point(1, 2);
+            var sum = point.sum;
+            point.x = 10;
+            var result = sum();
+            var again = point.init(3, 4);
+        ");
+
+        assert_eq!(global(&interpreter, "point"), "<Point instance>");
+        assert_eq!(global(&interpreter, "result"), "12");
+        assert_eq!(global(&interpreter, "again"), "<Point instance>");
+        assert_eq!(global(&interpreter, "Point"), "<class Point>");
+    }
+
+    #[test]
+    fn super_calls_resolve_against_the_superclass_chain() {
+        let interpreter = run("
+            class A { name() { return \"A\"; } }
+            class B < A {
+                name() { return \"B\"; }
+                parent() { return super.name(); }
+            }
+            class C < B {}
+
+            var own = C().name();
+            var parent = C().parent();
+        ");
+
+        assert_eq!(global(&interpreter, "own"), "B");
+        assert_eq!(global(&interpreter, "parent"), "A");
+    }
+
+    #[test]
+    fn exponent_evaluates_with_math_precedence() {
+        let interpreter = run("
+            var right_assoc = 2 ^ 3 ^ 2;
+            var negated = -2 ^ 2;
+            var negative_exponent = 2 ^ -1;
+            var grouped = (-2) ^ 2;
+            var with_product = 2 * 3 ^ 2;
+            var with_quotient = 2 ^ 3 / 4;
+            var fractional = 4 ^ 0.5;
+        ");
+
+        assert_eq!(global(&interpreter, "right_assoc"), "512");
+        assert_eq!(global(&interpreter, "negated"), "-4");
+        assert_eq!(global(&interpreter, "negative_exponent"), "0.5");
+        assert_eq!(global(&interpreter, "grouped"), "4");
+        assert_eq!(global(&interpreter, "with_product"), "18");
+        assert_eq!(global(&interpreter, "with_quotient"), "2");
+        assert_eq!(global(&interpreter, "fractional"), "2");
+    }
+
+    #[test]
+    fn collector_frees_unreachable_cycles() {
+        let interpreter = run("
+            class Node {}
+            for (var i = 0; i < 100; i = i + 1) {
+                var a = Node();
+                var b = Node();
+                a.other = b;
+                b.other = a;
+                fun recurse() { return recurse; }
+            }
+            var kept = Node();
+            kept.me = kept;
+        ");
+
+        interpreter.collect_garbage(None);
+
+        // The globals, and the fields of `kept`.
+        assert_eq!(interpreter.heap().live(), 2);
+        assert_eq!(global(&interpreter, "kept"), "<Node instance>");
+    }
+
+    #[test]
+    fn stress_mode_keeps_values_in_flight_alive() {
+        let config = GcConfig { stress: true, ..GcConfig::default() };
+        let interpreter = run_with("
+            class Box {
+                init(value) { this.value = value; }
+                get() { return this.value; }
+            }
+            fun box(value) { return Box(value); }
+            fun add(a, b) { return a.get() + b.get(); }
+
+            var sum = box(1).get() + box(2).get();
+            var same = box(1) == box(2);
+            var arguments = add(box(3), box(4));
+            var bound = box(\"kept\").get;
+            var called = bound();
+        ", config);
+
+        assert_eq!(global(&interpreter, "sum"), "3");
+        assert_eq!(global(&interpreter, "same"), "false");
+        assert_eq!(global(&interpreter, "arguments"), "7");
+        assert_eq!(global(&interpreter, "called"), "kept");
+    }
+
+    #[test]
+    fn native_errors_carry_the_call_site() {
+        let interpreter = run("");
+        let fail = NativeFunction::new(Symbol::intern("fail"), Arity::Variadic, Box::new(|_| Err(String::from("Refused."))));
+        interpreter.define_global(Symbol::intern("fail"), Object::Native(Rc::new(fail)));
+
+        let source = "var a = 1;\nfail(a,\n  2);";
+        let tokens = Scanner::new(source.as_bytes(), &Reporter::default()).scan_tokens().expect("test program should scan");
+        let statements = Parser::new(tokens, &Reporter::default()).parse().expect("test program should parse");
+        assert!(Resolver::new(&Reporter::default()).resolve(&statements).is_ok(), "test program should resolve");
 
+        interpreter.execute(&statements[0]).unwrap_or_else(|_| panic!("declaration should run"));
+        let Err(Unwind::Error(error)) = interpreter.execute(&statements[1]) else { panic!("the native should fail") };
+        assert_eq!(error.code, ErrorCode::NativeError);
+        assert_eq!(error.message, "Refused.");
+        assert_eq!((error.token.lexeme.as_str(), error.token.line), (")", 3));
     }
 }
@@ -0,0 +1,296 @@
+//! An implementation of the Lox language that can be embedded in a Rust
+//! program as a configuration or scripting language.
+//!
+//! ```
+//! use rlox::{Lox, Symbol, Value};
+//!
+//! let mut lox = Lox::new();
+//! lox.set_global("width", Value::Num(80.0));
+//! lox.eval("var height = width / 2;").unwrap();
+//!
+//! assert!(matches!(lox.get_global("height").as_deref(), Some(Value::Num(height)) if *height == 40.0));
+//! assert!(matches!(lox.eval("\"w\" + \"h\";").as_deref(), Ok(Value::Str(s)) if *s == Symbol::intern("wh")));
+//! ```
+//!
+//! Errors are reported on stderr as they are found, in the format chosen
+//! with [`Lox::set_error_format`]; the [`Error`] returned afterwards only says
+//! which phase failed. Each session keeps its own format, and diagnostics
+//! name the script that session is running.
+
+#[allow(dead_code)]
+mod ast_printer;
+mod callable;
+mod chunk;
+mod class;
+mod compiler;
+mod debug;
+mod environment;
+mod error;
+mod expr;
+mod heap;
+mod parser;
+mod resolver;
+mod scanner;
+mod serialize;
+mod stmt;
+mod symbol;
+mod token;
+mod token_type;
+mod interpreter;
+mod userdata;
+mod vm;
+
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::rc::Rc;
+
+use callable::NativeFunction;
+use compiler::Compiler;
+use interpreter::Interpreter;
+use parser::Parser;
+use resolver::Resolver;
+use scanner::Scanner;
+use stmt::Stmt;
+
+pub use callable::Arity;
+pub use error::{ErrorFormat, Reporter};
+pub use heap::{GcConfig, Rooted, Tracer};
+pub use serialize::LoadError;
+pub use symbol::Symbol;
+/// A Lox value, as handed between the host and scripts.
+pub use token::Object as Value;
+pub use userdata::UserData;
+
+/// Native stack one Lox call may take on the tree-walking interpreter, with
+/// room for the blocks and expressions nested inside it. Unoptimized builds
+/// need about four times as much.
+const CALL_STACK: usize = if cfg!(debug_assertions) { 32 * 1024 } else { 8 * 1024 };
+
+/// Native stack a thread should have to run scripts on [`Lox`]. The
+/// tree-walking interpreter recurses on the Rust stack for every Lox call, so
+/// this is sized for the deepest call stack a script may build; runaway
+/// recursion then ends in a runtime error instead of aborting the process.
+pub const STACK_SIZE: usize = vm::FRAMES_MAX * CALL_STACK;
+
+/// The syntax tree, for tools that work with Lox source rather than run it.
+pub mod syntax {
+    pub use crate::expr::Expr;
+    pub use crate::parser::LoxSyntaxErrors;
+    pub use crate::stmt::Stmt;
+    pub use crate::token::{Location, Span, Token};
+    pub use crate::token_type::TokenType;
+
+    /// Expression nodes and their visitor.
+    pub mod expr {
+        pub use crate::expr::*;
+    }
+
+    /// Statement nodes and their visitor.
+    pub mod stmt {
+        pub use crate::stmt::*;
+    }
+
+    use crate::{Parser, Reporter, Scanner};
+
+    /// Scans and parses `source` without resolving it, reporting any errors
+    /// through `reporter`. After syntax errors the declarations that did
+    /// parse are still returned in the error; source that fails to scan has
+    /// none.
+    pub fn parse(source: &str, reporter: &Reporter) -> Result<Vec<Stmt>, LoxSyntaxErrors> {
+        let Ok(tokens) = Scanner::new(source.as_bytes(), reporter).scan_tokens() else {
+            return Err(LoxSyntaxErrors { statements: Vec::new() });
+        };
+        Parser::new(tokens, reporter).parse()
+    }
+}
+
+/// The bytecode backend: a compiler to bytecode, its file format and the
+/// stack virtual machine that runs it.
+pub mod bytecode {
+    pub use crate::chunk::Function;
+    pub use crate::debug::disassemble_function;
+    pub use crate::serialize::{is_bytecode, serialize};
+    pub use crate::vm::Vm;
+
+    use std::rc::Rc;
+
+    use crate::{Error, Reporter};
+
+    /// Scans, parses, resolves and compiles `source`, reporting any errors
+    /// through `reporter`.
+    pub fn compile(source: &str, reporter: &Reporter) -> Result<Rc<Function>, Error> {
+        let statements = crate::front_end(source, reporter)?;
+        crate::Compiler::new(reporter).compile(&statements).map_err(|_| Error::Compile)
+    }
+
+    /// Loads bytecode written by [`serialize`].
+    pub fn load(bytes: &[u8]) -> Result<Rc<Function>, Error> {
+        crate::serialize::deserialize(bytes).map_err(Error::Load)
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    /// The source failed to scan, parse, resolve or compile.
+    Compile,
+    /// The program stopped on a runtime error.
+    Runtime,
+    /// A bytecode file was damaged or from another format version.
+    Load(LoadError),
+    /// A compiled file was run in a session that has natives or userdata,
+    /// which the bytecode VM can't use.
+    HostBindings,
+    /// A script couldn't be read.
+    Io(io::Error),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Compile => write!(f, "the program has compile errors"),
+            Error::Runtime => write!(f, "the program stopped on a runtime error"),
+            Error::Load(error) => write!(f, "could not load bytecode: {error}"),
+            Error::HostBindings => write!(f, "compiled scripts can't use the natives or userdata defined by the host"),
+            Error::Io(error) => write!(f, "{error}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+/// A Lox session on the tree-walking interpreter. Globals persist from one
+/// call to the next, so a host can run a script and then read back what it
+/// defined.
+///
+/// Scripts run on the calling thread's stack, which should be at least
+/// [`STACK_SIZE`]; see `std::thread::Builder::stack_size`.
+///
+/// Values read out of the session come back as [`Rooted`] handles, which
+/// keep them from being collected until they are dropped.
+pub struct Lox {
+    interpreter: Interpreter,
+    format: ErrorFormat,
+    /// Whether natives or userdata have been handed to scripts.
+    host_bindings: bool,
+}
+
+impl Default for Lox {
+    fn default() -> Self {
+        Lox::new()
+    }
+}
+
+impl Lox {
+    pub fn new() -> Self {
+        Lox::with_gc_config(GcConfig::default())
+    }
+
+    pub fn with_gc_config(config: GcConfig) -> Self {
+        Lox { interpreter: Interpreter::with_gc_config(config), format: ErrorFormat::default(), host_bindings: false }
+    }
+
+    /// Chooses how this session writes diagnostics to stderr.
+    pub fn set_error_format(&mut self, format: ErrorFormat) {
+        self.format = format;
+    }
+
+    /// Runs `source` and returns the value of its last statement if that is
+    /// an expression, or `nil` otherwise.
+    pub fn eval(&mut self, source: &str) -> Result<Rooted, Error> {
+        self.run_source("<eval>", source)
+    }
+
+    /// Like [`Lox::eval`], with `name` standing for the source in diagnostics.
+    pub fn run_source(&mut self, name: &str, source: &str) -> Result<Rooted, Error> {
+        let reporter = Reporter::new(self.format, name, Some(source));
+        let statements = front_end(source, &reporter)?;
+        let value = self.interpreter.interpret(&statements, &reporter).map_err(|_| Error::Runtime)?;
+        Ok(self.interpreter.root(value))
+    }
+
+    /// Runs a script, or a file written by [`bytecode::serialize`]. Compiled
+    /// files run on a fresh bytecode VM and can't see this session's globals.
+    /// The VM can't use natives or userdata either, so once the session has
+    /// any, compiled files fail with [`Error::HostBindings`].
+    pub fn run_file(&mut self, path: impl AsRef<Path>) -> Result<(), Error> {
+        let path = path.as_ref();
+        let name = path.display().to_string();
+        let content = fs::read(path).map_err(Error::Io)?;
+
+        if bytecode::is_bytecode(&content) {
+            if self.host_bindings {
+                return Err(Error::HostBindings);
+            }
+            let function = bytecode::load(&content)?;
+            let reporter = Reporter::new(self.format, &name, None);
+            return bytecode::Vm::new().interpret(function, &reporter).map_err(|_| Error::Runtime);
+        }
+
+        let source = String::from_utf8(content).map_err(|error| Error::Io(io::Error::new(io::ErrorKind::InvalidData, error)))?;
+        self.run_source(&name, &source).map(drop)
+    }
+
+    pub fn set_global(&mut self, name: &str, value: Value) {
+        self.host_bindings |= matches!(value, Value::Native(_) | Value::UserData(_));
+        self.interpreter.define_global(Symbol::intern(name), value);
+    }
+
+    pub fn get_global(&self, name: &str) -> Option<Rooted> {
+        self.interpreter.global(&Symbol::intern(name)).map(|value| self.interpreter.root(value))
+    }
+
+    /// Keeps `value`, such as one taken out of a [`Rooted`] or handed to a
+    /// native, alive until the returned handle is dropped.
+    pub fn root(&self, value: Value) -> Rooted {
+        self.interpreter.root(value)
+    }
+
+    /// Makes `function` callable from scripts as the global `name`. An `Err`
+    /// it returns becomes a runtime error reported at the call. Natives only
+    /// exist on the tree-walking interpreter; see [`Lox::run_file`].
+    ///
+    /// The collector can't see into the closure, so Lox values it captures
+    /// must be held as [`Rooted`] handles, as from [`Lox::root`].
+    ///
+    /// ```
+    /// use rlox::{Arity, Lox, Value};
+    ///
+    /// let mut lox = Lox::new();
+    /// lox.define_native("sum", Arity::Variadic, |arguments| {
+    ///     let mut sum = 0.0;
+    ///     for argument in arguments {
+    ///         let Value::Num(number) = argument else { return Err(String::from("Can only sum numbers.")) };
+    ///         sum += number;
+    ///     }
+    ///     Ok(Value::Num(sum))
+    /// });
+    ///
+    /// assert!(matches!(lox.eval("sum(1, 2, 3);").as_deref(), Ok(Value::Num(n)) if *n == 6.0));
+    /// ```
+    pub fn define_native(
+        &mut self,
+        name: &str,
+        arity: Arity,
+        function: impl Fn(&[Value]) -> Result<Value, String> + 'static,
+    ) {
+        let name = Symbol::intern(name);
+        let native = NativeFunction::new(name.clone(), arity, Box::new(function));
+        self.interpreter.define_global(name, Value::Native(Rc::new(native)));
+        self.host_bindings = true;
+    }
+}
+
+/// Everything before running: scanning, parsing and resolving.
+fn front_end(source: &str, reporter: &Reporter) -> Result<Vec<Stmt>, Error> {
+    let statements = syntax::parse(source, reporter).map_err(|_| Error::Compile)?;
+    Resolver::new(reporter).resolve(&statements).map_err(|_| Error::Compile)?;
+    Ok(statements)
+}
@@ -2,42 +2,155 @@ use std::env;
 use std::fs;
 use std::io::{self, Write};
 use std::process;
+use std::thread;
 
-mod ast_printer;
-mod error;
-mod expr;
-mod parser;
-mod scanner;
-mod token;
-mod token_type;
-mod interpreter;
+use rlox::bytecode::{self, Vm};
+use rlox::{Error, ErrorFormat, GcConfig, Lox, Reporter};
 
-use parser::Parser;
-use scanner::Scanner;
-use token::Token;
-use interpreter::Interpreter;
-
-static INTERPRETER: Interpreter = Interpreter;
+/// Which engine runs programs once they have been parsed and resolved.
+enum Backend {
+    Tree(Lox),
+    /// With `disassemble` set, compiled code is listed instead of run; with
+    /// `compile_to` set, it is saved to that file instead of run.
+    Bytecode { vm: Vm, disassemble: bool, compile_to: Option<String>, format: ErrorFormat },
+}
 
 fn main() {
-    let args: Vec<String> = env::args().collect();
-    match args.len() {
-        2 => run_file(&args[1]).expect("Could not run file."),
-        n if n > 2 => {
-            eprintln!("Usage: rlox [script]");
-            process::exit(64);
+    let session = thread::Builder::new()
+        .stack_size(rlox::STACK_SIZE)
+        .spawn(session)
+        .expect("Could not start the interpreter thread");
+    if session.join().is_err() {
+        process::exit(101);
+    }
+}
+
+fn session() {
+    let mut backend_flag = None;
+    let mut disassemble = false;
+    let mut trace = false;
+    let mut compile_to = None;
+    let mut gc_stress = false;
+    let mut format = ErrorFormat::Human;
+    let mut script = None;
+
+    for arg in env::args().skip(1) {
+        match arg.as_str() {
+            "--backend=tree" => backend_flag = Some(false),
+            "--backend=vm" => backend_flag = Some(true),
+            "--disassemble" => disassemble = true,
+            "--trace" => trace = true,
+            "--gc-stress" => gc_stress = true,
+            "--error-format=human" => format = ErrorFormat::Human,
+            "--error-format=json" => format = ErrorFormat::Json,
+            _ if arg.starts_with("--compile=") => compile_to = Some(arg["--compile=".len()..].to_string()),
+            _ if arg.starts_with("--") || script.is_some() => usage(),
+            _ => script = Some(arg),
+        }
+    }
+
+    // Inspecting bytecode only makes sense on the bytecode backend, so it
+    // picks that backend unless another one was asked for.
+    let inspects_bytecode = disassemble || trace || compile_to.is_some();
+    if inspects_bytecode && backend_flag == Some(false) {
+        eprintln!("--disassemble, --trace and --compile need --backend=vm.");
+        usage();
+    }
+
+    let config = GcConfig { stress: gc_stress, ..GcConfig::default() };
+    let mut backend = if backend_flag.unwrap_or(inspects_bytecode) {
+        let mut vm = Vm::with_gc_config(config);
+        vm.set_trace(trace);
+        Backend::Bytecode { vm, disassemble, compile_to, format }
+    } else {
+        let mut lox = Lox::with_gc_config(config);
+        lox.set_error_format(format);
+        Backend::Tree(lox)
+    };
+
+    match script {
+        Some(path) => {
+            if let Err(failure) = run_file(&mut backend, &path) {
+                process::exit(failure.exit_code());
+            }
+        }
+        None => run_prompt(&mut backend),
+    }
+}
+
+fn usage() -> ! {
+    eprintln!("Usage: rlox [--backend=tree|vm] [--disassemble] [--trace] [--compile=out] [--gc-stress] [--error-format=human|json] [script]");
+    process::exit(64);
+}
+
+/// Why a script didn't run to completion. Whatever went wrong has already
+/// been reported by the time one of these is returned.
+enum Failure {
+    /// The script failed to scan, parse, resolve or compile, or is a
+    /// damaged bytecode file.
+    Compile,
+    Runtime,
+    /// The script couldn't be read.
+    Input,
+    /// The compiled output couldn't be written.
+    Output,
+}
+
+impl Failure {
+    /// Reports whatever the diagnostics haven't already covered.
+    fn from_error(name: &str, error: Error) -> Failure {
+        match error {
+            Error::Compile => Failure::Compile,
+            Error::Runtime => Failure::Runtime,
+            Error::Load(error) => {
+                eprintln!("Could not load bytecode: {error}");
+                Failure::Compile
+            }
+            Error::Io(error) => {
+                eprintln!("Could not read {name}: {error}");
+                Failure::Input
+            }
+            Error::HostBindings => {
+                eprintln!("Could not run {name}: {error}");
+                Failure::Compile
+            }
+        }
+    }
+
+    /// Exit status, following the BSD `sysexits.h` convention.
+    fn exit_code(&self) -> i32 {
+        match self {
+            Failure::Compile => 65,
+            Failure::Runtime => 70,
+            Failure::Input => 66,
+            Failure::Output => 74,
         }
-        _ => run_prompt(),
     }
 }
 
-fn run_file(path: &String) -> io::Result<()> {
-    let content = fs::read_to_string(path)?;
-    run(content);
-    Ok(())
+fn run_file(backend: &mut Backend, path: &str) -> Result<(), Failure> {
+    let (vm, disassemble, format) = match backend {
+        Backend::Tree(lox) => return lox.run_file(path).map_err(|error| Failure::from_error(path, error)),
+        Backend::Bytecode { vm, disassemble, format, .. } => (vm, *disassemble, *format),
+    };
+
+    let content = fs::read(path).map_err(|error| Failure::from_error(path, Error::Io(error)))?;
+    if bytecode::is_bytecode(&content) {
+        // A file written by `--compile` is run or listed as it is.
+        let function = bytecode::load(&content).map_err(|error| Failure::from_error(path, error))?;
+        if disassemble {
+            print!("{}", bytecode::disassemble_function(&function));
+            return Ok(());
+        }
+        return vm.interpret(function, &Reporter::new(format, path, None)).map_err(|_| Failure::Runtime);
+    }
+
+    let source = String::from_utf8(content)
+        .map_err(|error| Failure::from_error(path, Error::Io(io::Error::new(io::ErrorKind::InvalidData, error))))?;
+    run(backend, path, &source)
 }
 
-fn run_prompt() {
+fn run_prompt(backend: &mut Backend) {
     loop {
         print!("> ");
         io::stdout().flush().expect("Could not flush");
@@ -47,19 +160,31 @@ fn run_prompt() {
                 if buff.is_empty() {
                     break;
                 };
-                run(buff);
+                // Errors have been reported; the session carries on.
+                let _ = run(backend, "<stdin>", &buff);
             }
             Err(_) => println!("There was an error, try again."),
         };
     }
 }
 
-fn run(source: String) {
-    let scanner = Scanner::new(source.as_bytes());
-    let tokens: Vec<Token> = scanner.scan_tokens();
-    let mut parser = Parser::new(tokens);
-    let Some(expression) = parser.parse() else { return };
-    
-    INTERPRETER.interpret(expression);
-    
+fn run(backend: &mut Backend, name: &str, source: &str) -> Result<(), Failure> {
+    match backend {
+        Backend::Tree(lox) => lox.run_source(name, source).map(drop).map_err(|error| Failure::from_error(name, error)),
+        Backend::Bytecode { vm, disassemble, compile_to, format } => {
+            let reporter = Reporter::new(*format, name, Some(source));
+            let function = bytecode::compile(source, &reporter).map_err(|error| Failure::from_error(name, error))?;
+            if let Some(path) = compile_to {
+                fs::write(&*path, bytecode::serialize(&function)).map_err(|error| {
+                    eprintln!("Could not write {path}: {error}");
+                    Failure::Output
+                })
+            } else if *disassemble {
+                print!("{}", bytecode::disassemble_function(&function));
+                Ok(())
+            } else {
+                vm.interpret(function, &reporter).map_err(|_| Failure::Runtime)
+            }
+        }
+    }
 }
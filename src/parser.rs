@@ -1,28 +1,326 @@
-use crate::{token::{Token, Object}, expr::{Expr, self}, token_type::TokenType, error::Lox};
+use std::cell::Cell;
+use std::rc::Rc;
 
-pub struct Parser {
+use crate::{token::{Token, Object}, expr::{Expr, self}, stmt::{Stmt, self}, token_type::TokenType, error::{Diagnostic, ErrorCode, Lox, Reporter}};
+
+pub struct Parser<'a> {
     tokens: Vec<Token>,
+    reporter: &'a Reporter,
     current: usize,
+    had_error: bool,
+    /// Index of the token the last error was reported at.
+    reported_at: Option<usize>,
 }
 
+/// Unwinds out of the rule that hit a syntax error, up to the nearest
+/// declaration, where the parser resynchronizes.
+#[derive(Debug)]
 pub struct LoxParseError;
 
-impl<'a> Parser {
-    pub fn new(tokens: Vec<Token>) -> Parser {
-        Parser { tokens, current: 0 }
+/// Returned by `parse` once every syntax error in the file has been reported.
+#[derive(Debug)]
+pub struct LoxSyntaxErrors {
+    /// The declarations that did parse, for tools that want to work with the
+    /// rest of the file.
+    pub statements: Vec<Stmt>,
+}
+
+const MAX_ARGUMENTS: usize = 255;
+
+impl<'a> Parser<'a> {
+    pub fn new(tokens: Vec<Token>, reporter: &'a Reporter) -> Self {
+        Parser { tokens, reporter, current: 0, had_error: false, reported_at: None }
+    }
+
+    pub fn parse(&mut self) -> Result<Vec<Stmt>, LoxSyntaxErrors> {
+        let mut statements = Vec::new();
+        while !self.is_at_end() {
+            statements.extend(self.recovering_declaration());
+        }
+
+        if self.had_error {
+            Err(LoxSyntaxErrors { statements })
+        } else {
+            Ok(statements)
+        }
+    }
+
+    /// Parses a declaration, or skips to the start of the next one after a
+    /// syntax error so that later errors are still reported.
+    fn recovering_declaration(&mut self) -> Option<Stmt> {
+        let start = self.current;
+        match self.declaration() {
+            Ok(stmt) => Some(stmt),
+            Err(LoxParseError) => {
+                self.synchronize();
+                // A stray '}' at the top level is left alone by `synchronize`.
+                if self.current == start {
+                    self.advance();
+                }
+                None
+            }
+        }
+    }
+
+    fn declaration(&mut self) -> Result<Stmt, LoxParseError> {
+        if self.match_tokens(&[TokenType::Class]) {
+            return self.class_declaration();
+        }
+
+        if self.match_tokens(&[TokenType::Fun]) {
+            return Ok(Stmt::Function(self.function("function")?));
+        }
+
+        if self.match_tokens(&[TokenType::Var]) {
+            return self.var_declaration();
+        }
+
+        self.statement()
+    }
+
+    fn class_declaration(&mut self) -> Result<Stmt, LoxParseError> {
+        let name = self.consume(&TokenType::Ident, String::from("Expect class name."))?.clone();
+
+        let superclass = if self.match_tokens(&[TokenType::Less]) {
+            let name = self.consume(&TokenType::Ident, String::from("Expect superclass name."))?.clone();
+            Some(expr::VariableExpr { name, depth: Cell::new(None) })
+        } else {
+            None
+        };
+
+        self.consume(&TokenType::LeftBrace, String::from("Expect '{' before class body."))?;
+
+        let mut methods = Vec::new();
+        while !self.check(&TokenType::RightBrace) && !self.is_at_end() {
+            methods.push(self.function("method")?);
+        }
+
+        self.consume(&TokenType::RightBrace, String::from("Expect '}' after class body."))?;
+        Ok(Stmt::Class(stmt::ClassStmt { name, superclass, methods }))
+    }
+
+    fn function(&mut self, kind: &str) -> Result<stmt::FunctionStmt, LoxParseError> {
+        let name = self.consume(&TokenType::Ident, format!("Expect {kind} name."))?.clone();
+        self.consume(&TokenType::LeftParen, format!("Expect '(' after {kind} name."))?;
+
+        let mut params = Vec::new();
+        if !self.check(&TokenType::RightParen) {
+            loop {
+                if params.len() >= MAX_ARGUMENTS {
+                    return Err(self.error(ErrorCode::TooManyParameters, format!("Can't have more than {MAX_ARGUMENTS} parameters.")));
+                }
+                params.push(self.consume(&TokenType::Ident, String::from("Expect parameter name."))?.clone());
+
+                if !self.match_tokens(&[TokenType::Comma]) { break; }
+            }
+        }
+        self.consume(&TokenType::RightParen, String::from("Expect ')' after parameters."))?;
+
+        self.consume(&TokenType::LeftBrace, format!("Expect '{{' before {kind} body."))?;
+        let body = Rc::new(self.block()?);
+
+        Ok(stmt::FunctionStmt { name, params, body })
+    }
+
+    fn var_declaration(&mut self) -> Result<Stmt, LoxParseError> {
+        let name = self.consume(&TokenType::Ident, String::from("Expect variable name."))?.clone();
+
+        let initializer = if self.match_tokens(&[TokenType::Assign]) {
+            Some(self.expression()?)
+        } else {
+            None
+        };
+
+        self.consume(&TokenType::SemiColon, String::from("Expect ';' after variable declaration."))?;
+        Ok(Stmt::Var(stmt::VarStmt { name, initializer }))
+    }
+
+    fn statement(&mut self) -> Result<Stmt, LoxParseError> {
+        if self.match_tokens(&[TokenType::For]) {
+            return self.for_statement();
+        }
+
+        if self.match_tokens(&[TokenType::If]) {
+            return self.if_statement();
+        }
+
+        if self.match_tokens(&[TokenType::While]) {
+            return self.while_statement();
+        }
+
+        if self.match_tokens(&[TokenType::Print]) {
+            return self.print_statement();
+        }
+
+        if self.match_tokens(&[TokenType::Return]) {
+            return self.return_statement();
+        }
+
+        if self.match_tokens(&[TokenType::LeftBrace]) {
+            return Ok(Stmt::Block(stmt::BlockStmt { statements: self.block()? }));
+        }
+
+        self.expression_statement()
+    }
+
+    fn for_statement(&mut self) -> Result<Stmt, LoxParseError> {
+        self.consume(&TokenType::LeftParen, String::from("Expect '(' after 'for'."))?;
+
+        let initializer = if self.match_tokens(&[TokenType::SemiColon]) {
+            None
+        } else if self.match_tokens(&[TokenType::Var]) {
+            Some(self.var_declaration()?)
+        } else {
+            Some(self.expression_statement()?)
+        };
+
+        let condition = if !self.check(&TokenType::SemiColon) {
+            Some(self.expression()?)
+        } else {
+            None
+        };
+        let semicolon = self.consume(&TokenType::SemiColon, String::from("Expect ';' after loop condition."))?.span;
+
+        let increment = if !self.check(&TokenType::RightParen) {
+            Some(self.expression()?)
+        } else {
+            None
+        };
+        self.consume(&TokenType::RightParen, String::from("Expect ')' after for clauses."))?;
+
+        let mut body = self.statement()?;
+
+        // Desugar into a while loop: { initializer; while (condition) { body; increment; } }
+        if let Some(increment) = increment {
+            body = Stmt::Block(stmt::BlockStmt {
+                statements: vec![body, Stmt::Expression(stmt::ExpressionStmt { expr: increment })],
+            });
+        }
+
+        let condition = condition.unwrap_or(Expr::Literal(expr::LiteralExpr { value: Object::Boolean(true), span: semicolon }));
+        body = Stmt::While(stmt::WhileStmt { condition, body: Box::new(body) });
+
+        if let Some(initializer) = initializer {
+            body = Stmt::Block(stmt::BlockStmt { statements: vec![initializer, body] });
+        }
+
+        Ok(body)
     }
 
-    pub fn parse(&mut self) -> Option<Expr> {
-        match self.expression() {
-            Ok(expr) => Some(expr),
-            Err(_) => None,
+    fn if_statement(&mut self) -> Result<Stmt, LoxParseError> {
+        self.consume(&TokenType::LeftParen, String::from("Expect '(' after 'if'."))?;
+        let condition = self.expression()?;
+        self.consume(&TokenType::RightParen, String::from("Expect ')' after if condition."))?;
+
+        let then_branch = Box::new(self.statement()?);
+        // An `else` binds to the nearest preceding `if`.
+        let else_branch = if self.match_tokens(&[TokenType::Else]) {
+            Some(Box::new(self.statement()?))
+        } else {
+            None
+        };
+
+        Ok(Stmt::If(stmt::IfStmt { condition, then_branch, else_branch }))
+    }
+
+    fn while_statement(&mut self) -> Result<Stmt, LoxParseError> {
+        self.consume(&TokenType::LeftParen, String::from("Expect '(' after 'while'."))?;
+        let condition = self.expression()?;
+        self.consume(&TokenType::RightParen, String::from("Expect ')' after condition."))?;
+        let body = Box::new(self.statement()?);
+
+        Ok(Stmt::While(stmt::WhileStmt { condition, body }))
+    }
+
+    fn print_statement(&mut self) -> Result<Stmt, LoxParseError> {
+        let expr = self.expression()?;
+        self.consume(&TokenType::SemiColon, String::from("Expect ';' after value."))?;
+        Ok(Stmt::Print(stmt::PrintStmt { expr }))
+    }
+
+    fn return_statement(&mut self) -> Result<Stmt, LoxParseError> {
+        let keyword = self.previous().clone();
+        let value = if !self.check(&TokenType::SemiColon) {
+            Some(self.expression()?)
+        } else {
+            None
+        };
+
+        self.consume(&TokenType::SemiColon, String::from("Expect ';' after return value."))?;
+        Ok(Stmt::Return(stmt::ReturnStmt { keyword, value }))
+    }
+
+    fn block(&mut self) -> Result<Vec<Stmt>, LoxParseError> {
+        let mut statements = Vec::new();
+
+        while !self.check(&TokenType::RightBrace) && !self.is_at_end() {
+            statements.extend(self.recovering_declaration());
         }
+
+        self.consume(&TokenType::RightBrace, String::from("Expect '}' after block."))?;
+        Ok(statements)
+    }
+
+    fn expression_statement(&mut self) -> Result<Stmt, LoxParseError> {
+        let expr = self.expression()?;
+        self.consume(&TokenType::SemiColon, String::from("Expect ';' after expression."))?;
+        Ok(Stmt::Expression(stmt::ExpressionStmt { expr }))
     }
 
     fn expression(&mut self) -> Result<Expr, LoxParseError> {
-        self.equality()
+        self.assignment()
+    }
+
+    fn assignment(&mut self) -> Result<Expr, LoxParseError> {
+        let expr = self.or()?;
+
+        if self.match_tokens(&[TokenType::Assign]) {
+            let value = self.assignment()?;
+
+            match expr {
+                Expr::Variable(variable) => {
+                    return Ok(Expr::Assign(expr::AssignExpr { name: variable.name, value: Box::new(value), depth: Cell::new(None) }));
+                }
+                Expr::Get(get) => {
+                    return Ok(Expr::Set(expr::SetExpr { object: get.object, name: get.name, value: Box::new(value) }));
+                }
+                target => {
+                    Diagnostic::at(ErrorCode::InvalidAssignmentTarget, "Invalid assignment target.", target.span())
+                        .with_label("can't be assigned to")
+                        .report(self.reporter);
+                    self.had_error = true;
+                    self.reported_at = Some(self.current);
+                    return Err(LoxParseError);
+                }
+            }
+        }
+
+        Ok(expr)
     }
 
+    fn or(&mut self) -> Result<Expr, LoxParseError> {
+        let mut expr = self.and()?;
+
+        while self.match_tokens(&[TokenType::Or]) {
+            let operator = self.previous().clone();
+            let right = self.and()?;
+            expr = Expr::Logical(expr::LogicalExpr { left: Box::new(expr), operator, right: Box::new(right) });
+        }
+
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> Result<Expr, LoxParseError> {
+        let mut expr = self.equality()?;
+
+        while self.match_tokens(&[TokenType::And]) {
+            let operator = self.previous().clone();
+            let right = self.equality()?;
+            expr = Expr::Logical(expr::LogicalExpr { left: Box::new(expr), operator, right: Box::new(right) });
+        }
+
+        Ok(expr)
+    }
 
     fn equality(&mut self) -> Result<Expr, LoxParseError> {
         let mut expr = self.comparison()?;
@@ -61,72 +359,123 @@ impl<'a> Parser {
     }
 
     fn factor(&mut self) -> Result<Expr, LoxParseError> {
-        let mut expr = self.power()?;
+        let mut expr = self.unary()?;
 
         while self.match_tokens(&[TokenType::Slash, TokenType::Star]) {
             let operator = self.previous().clone();
-            let right = self.power()?;
+            let right = self.unary()?;
             expr = Expr::Binary(expr::BinaryExpr { left: Box::new(expr), operator, right: Box::new(right) });
         }
 
         Ok(expr)
     }
 
+    fn unary(&mut self) -> Result<Expr, LoxParseError> {
+        if self.match_tokens(&[TokenType::Bang, TokenType::Minus]) {
+            let operator = self.previous().clone();
+            let right = self.unary()?; 
+            return Ok(Expr::Unary(expr::UnaryExpr { operator, right: Box::new(right) }));
+        }
+
+        self.power()
+    }
+
+    // `^` binds tighter than prefix operators, so `-2^2` is `-(2^2)`, and
+    // recursing through `unary` makes it right-associative and allows `2^-1`.
     fn power(&mut self) -> Result<Expr, LoxParseError> {
-        let mut expr = self.unary()?;
+        let expr = self.call()?;
 
-        while self.match_tokens(&[TokenType::Exponent]) {
+        if self.match_tokens(&[TokenType::Exponent]) {
             let operator = self.previous().clone();
             let right = self.unary()?;
-            expr = Expr::Binary(expr::BinaryExpr{ left: Box::new(expr), operator, right: Box::new(right) });
+            return Ok(Expr::Binary(expr::BinaryExpr { left: Box::new(expr), operator, right: Box::new(right) }));
         }
 
         Ok(expr)
     }
 
-    fn unary(&mut self) -> Result<Expr, LoxParseError> {
-        if self.match_tokens(&[TokenType::Bang, TokenType::Minus]) {
-            let operator = self.previous().clone();
-            let right = self.unary()?; 
-            return Ok(Expr::Unary(expr::UnaryExpr { operator, right: Box::new(right) }));
+    fn call(&mut self) -> Result<Expr, LoxParseError> {
+        let mut expr = self.primary()?;
+
+        loop {
+            if self.match_tokens(&[TokenType::LeftParen]) {
+                expr = self.finish_call(expr)?;
+            } else if self.match_tokens(&[TokenType::Dot]) {
+                let name = self.consume(&TokenType::Ident, String::from("Expect property name after '.'."))?.clone();
+                expr = Expr::Get(expr::GetExpr { object: Box::new(expr), name });
+            } else {
+                break;
+            }
+        }
+
+        Ok(expr)
+    }
+
+    fn finish_call(&mut self, callee: Expr) -> Result<Expr, LoxParseError> {
+        let mut arguments = Vec::new();
+        if !self.check(&TokenType::RightParen) {
+            loop {
+                if arguments.len() >= MAX_ARGUMENTS {
+                    return Err(self.error(ErrorCode::TooManyArguments, format!("Can't have more than {MAX_ARGUMENTS} arguments.")));
+                }
+                arguments.push(self.expression()?);
+
+                if !self.match_tokens(&[TokenType::Comma]) { break; }
+            }
         }
 
-        self.primary()
+        let paren = self.consume(&TokenType::RightParen, String::from("Expect ')' after arguments."))?.clone();
+
+        Ok(Expr::Call(expr::CallExpr { callee: Box::new(callee), paren, arguments }))
     }
 
     fn primary(&mut self) -> Result<Expr, LoxParseError> {
         if self.match_tokens(&[TokenType::False]) { 
-            return Ok(Expr::Literal(expr::LiteralExpr { value: Object::Boolean(false) }));
+            return Ok(Expr::Literal(expr::LiteralExpr { value: Object::Boolean(false), span: self.previous().span }));
         }
 
         if self.match_tokens(&[TokenType::True]) { 
-            return Ok(Expr::Literal(expr::LiteralExpr { value: Object::Boolean(true) }));
+            return Ok(Expr::Literal(expr::LiteralExpr { value: Object::Boolean(true), span: self.previous().span }));
         }
 
         if self.match_tokens(&[TokenType::Nil]) { 
-            return Ok(Expr::Literal(expr::LiteralExpr { value: Object::Nil }));
+            return Ok(Expr::Literal(expr::LiteralExpr { value: Object::Nil, span: self.previous().span }));
         }
 
         if self.match_tokens(&[TokenType::Number, TokenType::String]) {
-            return Ok(Expr::Literal(expr::LiteralExpr { value: self.previous().literal.clone().unwrap() }));
+            let token = self.previous();
+            return Ok(Expr::Literal(expr::LiteralExpr { value: token.literal.clone().unwrap(), span: token.span }));
+        }
+
+        if self.match_tokens(&[TokenType::Super]) {
+            let keyword = self.previous().clone();
+            self.consume(&TokenType::Dot, String::from("Expect '.' after 'super'."))?;
+            let method = self.consume(&TokenType::Ident, String::from("Expect superclass method name."))?.clone();
+            return Ok(Expr::Super(expr::SuperExpr { keyword, method, depth: Cell::new(None) }));
+        }
+
+        if self.match_tokens(&[TokenType::This]) {
+            return Ok(Expr::This(expr::ThisExpr { keyword: self.previous().clone(), depth: Cell::new(None) }));
+        }
+
+        if self.match_tokens(&[TokenType::Ident]) {
+            return Ok(Expr::Variable(expr::VariableExpr { name: self.previous().clone(), depth: Cell::new(None) }));
         }
 
         if self.match_tokens(&[TokenType::LeftParen]) {
-            let expr = match self.expression() {
-                Ok(expr) => expr,
-                Err(err) => return Err(err),
-            };
-            self.consume(&TokenType::RightParen, String::from("Expect '(' after expression."))?;
-            return Ok(Expr::Grouping(expr::GroupingExpr { expr: Box::new(expr) }));
+            let open = self.previous().span;
+            let expr = self.expression()?;
+            let close = self.consume(&TokenType::RightParen, String::from("Expect ')' after expression."))?.span;
+            return Ok(Expr::Grouping(expr::GroupingExpr { expr: Box::new(expr), span: open.to(close) }));
         }
         
-        Err(Parser::error(self.peek(), "Expect expression.".to_string()))
+        Err(self.error(ErrorCode::ExpectedExpression, "Expect expression.".to_string()))
     }
 
-    fn consume(&'a mut self, ttype: &'a TokenType, error_msg: String) -> Result<&Token, LoxParseError> {
+    fn consume(&mut self, ttype: &TokenType, error_msg: String) -> Result<&Token, LoxParseError> {
         if self.check(ttype) { return Ok(self.advance()); }
 
-        Err(Parser::error(self.peek(), error_msg))
+        Err(self.error(ErrorCode::ExpectedToken, error_msg))
     }
 
     fn match_tokens(&mut self, types: &[TokenType]) -> bool {
@@ -163,18 +512,31 @@ impl<'a> Parser {
         self.tokens.get(self.current - 1).expect("Couldn't get previous token.")
     }
 
-    fn error(token: &Token, message: String) -> LoxParseError {
-        Lox::parse_error(token, message);
+    /// Reports an error at the current token. Nested rules that give up at
+    /// the same token, like unclosed blocks at the end of the file, are only
+    /// reported once.
+    fn error(&mut self, code: ErrorCode, message: String) -> LoxParseError {
+        if self.reported_at != Some(self.current) {
+            Lox::parse_error(code, self.peek(), message).report(self.reporter);
+        }
+        self.had_error = true;
+        self.reported_at = Some(self.current);
         LoxParseError
     }
 
+    /// Skips tokens up to a likely statement boundary after a syntax error.
+    /// A keyword that starts a statement is kept even when it is the token
+    /// that caused the error, since that's usually a missing ';' before it.
+    /// Blocks opened by skipped tokens are skipped whole, so their '}' isn't
+    /// taken for the end of the enclosing block.
     fn synchronize(&mut self) {
-        self.advance();
+        let mut depth = 0;
 
         while !self.is_at_end() {
-            if self.previous().ttype == TokenType::SemiColon { return; }
-
             match self.peek().ttype {
+                TokenType::RightBrace if depth == 0 => return,
+                TokenType::RightBrace => depth -= 1,
+                TokenType::LeftBrace => depth += 1,
                 TokenType::If 
                 | TokenType::Class 
                 | TokenType::Var
@@ -182,9 +544,90 @@ impl<'a> Parser {
                 | TokenType::For
                 | TokenType::While
                 | TokenType::Print
-                | TokenType::Return => return,
-                _ => self.advance(),
-            };
+                | TokenType::Return if depth == 0 => return,
+                _ => {}
+            }
+
+            self.advance();
+            if depth == 0 && matches!(self.previous().ttype, TokenType::SemiColon | TokenType::RightBrace) {
+                return;
+            }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast_printer::AstPrinter;
+    use crate::scanner::Scanner;
+
+    fn parse_expression(source: &str) -> Expr {
+        let tokens = Scanner::new(format!("{source};").as_bytes(), &Reporter::default()).scan_tokens().expect("test expression should scan");
+        let statements = Parser::new(tokens, &Reporter::default()).parse().expect("test expression should parse");
+        match statements.into_iter().next() {
+            Some(Stmt::Expression(stmt)) => stmt.expr,
+            _ => panic!("expected a single expression statement"),
+        }
+    }
+
+    fn print_expression(source: &str) -> String {
+        AstPrinter::print(&parse_expression(source), &AstPrinter)
+    }
+
+    #[test]
+    fn exponent_is_right_associative() {
+        assert_eq!(print_expression("2 ^ 3 ^ 2"), "(^ 2 (^ 3 2))");
+    }
+
+    #[test]
+    fn exponent_binds_tighter_than_unary_operators() {
+        assert_eq!(print_expression("-2 ^ 2"), "(- (^ 2 2))");
+        assert_eq!(print_expression("!a ^ b"), "(! (^ a b))");
+        assert_eq!(print_expression("2 ^ -1"), "(^ 2 (- 1))");
+        assert_eq!(print_expression("-2 ^ -2 ^ 2"), "(- (^ 2 (- (^ 2 2))))");
+    }
+
+    #[test]
+    fn exponent_binds_tighter_than_factors() {
+        assert_eq!(print_expression("2 * 3 ^ 2"), "(* 2 (^ 3 2))");
+        assert_eq!(print_expression("2 ^ 3 * 2"), "(* (^ 2 3) 2)");
+        assert_eq!(print_expression("a.b ^ f(2)"), "(^ (. b a) (call f 2))");
+    }
+
+    #[test]
+    fn expression_spans_cover_all_their_tokens_across_lines() {
+        let source = "(a +\n  b) * f(1, \"x\")";
+        let expr = parse_expression(source);
+
+        let span = expr.span();
+        assert_eq!(&source[span.start.offset..span.end.offset], source);
+        assert_eq!((span.start.line, span.start.column), (1, 1));
+        assert_eq!((span.end.line, span.end.column), (2, 17));
+
+        let Expr::Binary(binary) = expr else { panic!("expected a binary expression") };
+        let left = binary.left.span();
+        assert_eq!(&source[left.start.offset..left.end.offset], "(a +\n  b)");
+        let right = binary.right.span();
+        assert_eq!(&source[right.start.offset..right.end.offset], "f(1, \"x\")");
+    }
+
+    #[test]
+    fn recovers_at_statement_boundaries_and_keeps_what_parsed() {
+        let source = "var a = 1 +;\nprint a\nvar b = 2;\n{ print (1; print 3; }\nif (b { print 5; }\nprint 4;";
+        let tokens = Scanner::new(source.as_bytes(), &Reporter::default()).scan_tokens().expect("test program should scan");
+        let Err(errors) = Parser::new(tokens, &Reporter::default()).parse() else { panic!("test program should not parse") };
+
+        let kinds: Vec<&str> = errors
+            .statements
+            .iter()
+            .map(|stmt| match stmt {
+                Stmt::Var(var) if var.name.lexeme == "b" => "var b",
+                Stmt::Block(block) if matches!(block.statements[..], [Stmt::Print(_)]) => "block",
+                Stmt::Print(_) => "print",
+                _ => "other",
+            })
+            .collect();
+        assert_eq!(kinds, ["var b", "block", "print"]);
+    }
+}
@@ -0,0 +1,366 @@
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+
+use crate::error::{ErrorCode, Lox, Reporter};
+use crate::expr::{self, Expr};
+use crate::stmt::{self, Stmt};
+use crate::symbol::Symbol;
+use crate::token::Token;
+
+/// Static pass run between parsing and interpretation. It records, on every
+/// variable use, how many scopes away its binding lives and reports misuse
+/// that can be caught without running the program.
+pub struct Resolver<'a> {
+    reporter: &'a Reporter,
+    /// One map per open local scope; the flag tells whether the variable's
+    /// initializer has finished, so a local can't be read while being defined.
+    scopes: RefCell<Vec<HashMap<Symbol, bool>>>,
+    current_function: Cell<FunctionType>,
+    current_class: Cell<ClassType>,
+    had_error: Cell<bool>,
+}
+
+pub struct LoxResolveError;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FunctionType {
+    None,
+    Function,
+    Method,
+    Initializer,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ClassType {
+    None,
+    Class,
+    Subclass,
+}
+
+impl expr::Visitor<()> for Resolver<'_> {
+    fn visit_binary(&self, binary: &expr::BinaryExpr) {
+        self.resolve_expr(&binary.left);
+        self.resolve_expr(&binary.right);
+    }
+
+    fn visit_grouping(&self, grouping: &expr::GroupingExpr) {
+        self.resolve_expr(&grouping.expr);
+    }
+
+    fn visit_unary(&self, unary: &expr::UnaryExpr) {
+        self.resolve_expr(&unary.right);
+    }
+
+    fn visit_literal(&self, _literal: &expr::LiteralExpr) {}
+
+    fn visit_variable(&self, variable: &expr::VariableExpr) {
+        let in_own_initializer = self
+            .scopes
+            .borrow()
+            .last()
+            .is_some_and(|scope| scope.get(&variable.name.lexeme) == Some(&false));
+
+        if in_own_initializer {
+            self.error(ErrorCode::ReadInOwnInitializer, &variable.name, "Can't read local variable in its own initializer.");
+        }
+
+        variable.depth.set(self.resolve_local(&variable.name));
+    }
+
+    fn visit_assign(&self, assign: &expr::AssignExpr) {
+        self.resolve_expr(&assign.value);
+        assign.depth.set(self.resolve_local(&assign.name));
+    }
+
+    fn visit_logical(&self, logical: &expr::LogicalExpr) {
+        self.resolve_expr(&logical.left);
+        self.resolve_expr(&logical.right);
+    }
+
+    fn visit_call(&self, call: &expr::CallExpr) {
+        self.resolve_expr(&call.callee);
+        for argument in &call.arguments {
+            self.resolve_expr(argument);
+        }
+    }
+
+    fn visit_get(&self, get: &expr::GetExpr) {
+        self.resolve_expr(&get.object);
+    }
+
+    fn visit_set(&self, set: &expr::SetExpr) {
+        self.resolve_expr(&set.value);
+        self.resolve_expr(&set.object);
+    }
+
+    fn visit_super(&self, super_expr: &expr::SuperExpr) {
+        match self.current_class.get() {
+            ClassType::None => self.error(ErrorCode::SuperOutsideClass, &super_expr.keyword, "Can't use 'super' outside of a class."),
+            ClassType::Class => self.error(ErrorCode::SuperWithoutSuperclass, &super_expr.keyword, "Can't use 'super' in a class with no superclass."),
+            ClassType::Subclass => super_expr.depth.set(self.resolve_local(&super_expr.keyword)),
+        }
+    }
+
+    fn visit_this(&self, this: &expr::ThisExpr) {
+        if self.current_class.get() == ClassType::None {
+            self.error(ErrorCode::ThisOutsideClass, &this.keyword, "Can't use 'this' outside of a class.");
+            return;
+        }
+
+        this.depth.set(self.resolve_local(&this.keyword));
+    }
+}
+
+impl stmt::Visitor<()> for Resolver<'_> {
+    fn visit_expression(&self, stmt: &stmt::ExpressionStmt) {
+        self.resolve_expr(&stmt.expr);
+    }
+
+    fn visit_print(&self, stmt: &stmt::PrintStmt) {
+        self.resolve_expr(&stmt.expr);
+    }
+
+    fn visit_var(&self, stmt: &stmt::VarStmt) {
+        self.declare(&stmt.name);
+        if let Some(initializer) = &stmt.initializer {
+            self.resolve_expr(initializer);
+        }
+        self.define(&stmt.name);
+    }
+
+    fn visit_block(&self, stmt: &stmt::BlockStmt) {
+        self.begin_scope();
+        self.resolve_stmts(&stmt.statements);
+        self.end_scope();
+    }
+
+    fn visit_if(&self, stmt: &stmt::IfStmt) {
+        self.resolve_expr(&stmt.condition);
+        self.resolve_stmt(&stmt.then_branch);
+        if let Some(else_branch) = &stmt.else_branch {
+            self.resolve_stmt(else_branch);
+        }
+    }
+
+    fn visit_while(&self, stmt: &stmt::WhileStmt) {
+        self.resolve_expr(&stmt.condition);
+        self.resolve_stmt(&stmt.body);
+    }
+
+    fn visit_function(&self, stmt: &stmt::FunctionStmt) {
+        // Define eagerly so the function can refer to itself recursively.
+        self.declare(&stmt.name);
+        self.define(&stmt.name);
+
+        self.resolve_function(stmt, FunctionType::Function);
+    }
+
+    fn visit_return(&self, stmt: &stmt::ReturnStmt) {
+        if self.current_function.get() == FunctionType::None {
+            self.error(ErrorCode::ReturnFromTopLevel, &stmt.keyword, "Can't return from top-level code.");
+        }
+
+        if let Some(value) = &stmt.value {
+            if self.current_function.get() == FunctionType::Initializer {
+                self.error(ErrorCode::ReturnValueFromInitializer, &stmt.keyword, "Can't return a value from an initializer.");
+            }
+            self.resolve_expr(value);
+        }
+    }
+
+    fn visit_class(&self, stmt: &stmt::ClassStmt) {
+        let enclosing_class = self.current_class.replace(ClassType::Class);
+
+        self.declare(&stmt.name);
+        self.define(&stmt.name);
+
+        if let Some(superclass) = &stmt.superclass {
+            if superclass.name.lexeme == stmt.name.lexeme {
+                self.error(ErrorCode::InheritFromSelf, &superclass.name, "A class can't inherit from itself.");
+            }
+
+            self.current_class.set(ClassType::Subclass);
+            superclass.accept(self);
+
+            self.begin_scope();
+            if let Some(scope) = self.scopes.borrow_mut().last_mut() {
+                scope.insert(Symbol::intern("super"), true);
+            }
+        }
+
+        self.begin_scope();
+        if let Some(scope) = self.scopes.borrow_mut().last_mut() {
+            scope.insert(Symbol::intern("this"), true);
+        }
+
+        for method in &stmt.methods {
+            let kind = if method.name.lexeme == "init" {
+                FunctionType::Initializer
+            } else {
+                FunctionType::Method
+            };
+            self.resolve_function(method, kind);
+        }
+
+        self.end_scope();
+
+        if stmt.superclass.is_some() {
+            self.end_scope();
+        }
+
+        self.current_class.set(enclosing_class);
+    }
+}
+
+impl<'a> Resolver<'a> {
+    pub fn new(reporter: &'a Reporter) -> Self {
+        Resolver {
+            reporter,
+            scopes: RefCell::new(Vec::new()),
+            current_function: Cell::new(FunctionType::None),
+            current_class: Cell::new(ClassType::None),
+            had_error: Cell::new(false),
+        }
+    }
+
+    pub fn resolve(&self, statements: &[Stmt]) -> Result<(), LoxResolveError> {
+        self.resolve_stmts(statements);
+
+        if self.had_error.get() {
+            Err(LoxResolveError)
+        } else {
+            Ok(())
+        }
+    }
+
+    fn resolve_stmts(&self, statements: &[Stmt]) {
+        for statement in statements {
+            self.resolve_stmt(statement);
+        }
+    }
+
+    fn resolve_stmt(&self, stmt: &Stmt) {
+        match stmt {
+            Stmt::Expression(stmt) => stmt.accept(self),
+            Stmt::Print(stmt) => stmt.accept(self),
+            Stmt::Var(stmt) => stmt.accept(self),
+            Stmt::Block(stmt) => stmt.accept(self),
+            Stmt::If(stmt) => stmt.accept(self),
+            Stmt::While(stmt) => stmt.accept(self),
+            Stmt::Function(stmt) => stmt.accept(self),
+            Stmt::Return(stmt) => stmt.accept(self),
+            Stmt::Class(stmt) => stmt.accept(self),
+        }
+    }
+
+    fn resolve_expr(&self, expr: &Expr) {
+        match expr {
+            Expr::Binary(expr) => expr.accept(self),
+            Expr::Grouping(expr) => expr.accept(self),
+            Expr::Unary(expr) => expr.accept(self),
+            Expr::Literal(expr) => expr.accept(self),
+            Expr::Variable(expr) => expr.accept(self),
+            Expr::Assign(expr) => expr.accept(self),
+            Expr::Logical(expr) => expr.accept(self),
+            Expr::Call(expr) => expr.accept(self),
+            Expr::Get(expr) => expr.accept(self),
+            Expr::Set(expr) => expr.accept(self),
+            Expr::This(expr) => expr.accept(self),
+            Expr::Super(expr) => expr.accept(self),
+        }
+    }
+
+    fn resolve_function(&self, function: &stmt::FunctionStmt, kind: FunctionType) {
+        let enclosing_function = self.current_function.replace(kind);
+
+        self.begin_scope();
+        for param in &function.params {
+            self.declare(param);
+            self.define(param);
+        }
+        self.resolve_stmts(&function.body);
+        self.end_scope();
+
+        self.current_function.set(enclosing_function);
+    }
+
+    /// Number of scopes between the use and its binding, or `None` for globals.
+    fn resolve_local(&self, name: &Token) -> Option<usize> {
+        self.scopes
+            .borrow()
+            .iter()
+            .rev()
+            .position(|scope| scope.contains_key(&name.lexeme))
+    }
+
+    fn begin_scope(&self) {
+        self.scopes.borrow_mut().push(HashMap::new());
+    }
+
+    fn end_scope(&self) {
+        self.scopes.borrow_mut().pop();
+    }
+
+    fn declare(&self, name: &Token) {
+        let mut scopes = self.scopes.borrow_mut();
+        let Some(scope) = scopes.last_mut() else { return };
+
+        if scope.contains_key(&name.lexeme) {
+            self.error(ErrorCode::AlreadyDeclared, name, "Already a variable with this name in this scope.");
+            return;
+        }
+
+        scope.insert(name.lexeme.clone(), false);
+    }
+
+    fn define(&self, name: &Token) {
+        if let Some(scope) = self.scopes.borrow_mut().last_mut() {
+            scope.insert(name.lexeme.clone(), true);
+        }
+    }
+
+    fn error(&self, code: ErrorCode, token: &Token, message: &str) {
+        Lox::resolve_error(code, token, message.to_string()).report(self.reporter);
+        self.had_error.set(true);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Parser;
+    use crate::scanner::Scanner;
+
+    fn resolve(source: &str) -> Result<(), LoxResolveError> {
+        let tokens = Scanner::new(source.as_bytes(), &Reporter::default()).scan_tokens().expect("test program should scan");
+        let statements = Parser::new(tokens, &Reporter::default()).parse().expect("test program should parse");
+        Resolver::new(&Reporter::default()).resolve(&statements)
+    }
+
+    #[test]
+    fn rejects_reading_local_in_its_own_initializer() {
+        assert!(resolve("{ var a = a; }").is_err());
+        assert!(resolve("var a = a;").is_ok());
+    }
+
+    #[test]
+    fn rejects_redeclaring_local_in_same_scope() {
+        assert!(resolve("{ var a = 1; var a = 2; }").is_err());
+        assert!(resolve("{ var a = 1; { var a = 2; } }").is_ok());
+        assert!(resolve("var a = 1; var a = 2;").is_ok());
+    }
+
+    #[test]
+    fn rejects_top_level_return() {
+        assert!(resolve("return 1;").is_err());
+        assert!(resolve("fun f() { return 1; }").is_ok());
+    }
+
+    #[test]
+    fn rejects_invalid_inheritance_and_super() {
+        assert!(resolve("class A < A {}").is_err());
+        assert!(resolve("fun f() { super.g(); }").is_err());
+        assert!(resolve("class A { f() { super.f(); } }").is_err());
+        assert!(resolve("class A {} class B < A { f() { super.f(); } }").is_ok());
+    }
+}
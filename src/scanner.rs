@@ -1,37 +1,60 @@
 use crate::{
-    error::Lox,
-    token::{Object, Token},
+    error::{Diagnostic, ErrorCode, Reporter},
+    symbol::Symbol,
+    token::{Location, Object, Span, Token},
     token_type::TokenType,
 };
 
 pub struct Scanner<'a> {
     source: &'a [u8],
+    reporter: &'a Reporter,
     tokens: Vec<Token>,
     start: usize,
     current: usize,
     line: usize,
+    /// Column of the character at `current`, kept up to date by `advance`.
+    column: usize,
+    /// Where the token being scanned starts.
+    start_location: Location,
+    had_error: bool,
 }
 
+#[derive(Debug)]
+pub struct LoxScanError;
+
 impl<'a> Scanner<'a> {
-    pub fn new(source: &'a [u8]) -> Self {
+    pub fn new(source: &'a [u8], reporter: &'a Reporter) -> Self {
         Scanner {
             source,
+            reporter,
             tokens: Vec::new(),
             start: 0,
             current: 0,
             line: 1,
+            column: 1,
+            start_location: Location { offset: 0, line: 1, column: 1 },
+            had_error: false,
         }
     }
 
-    pub fn scan_tokens(mut self) -> Vec<Token> {
+    /// Scans the whole source, reporting every bad character or string
+    /// rather than stopping at the first.
+    pub fn scan_tokens(mut self) -> Result<Vec<Token>, LoxScanError> {
         while !self.is_at_end() {
             self.start = self.current;
+            self.start_location = self.location();
             self.scan_token();
         }
 
+        let end = self.location();
         self.tokens
-            .push(Token::new(TokenType::Eof, String::new(), None, self.line));
-        self.tokens
+            .push(Token::new(TokenType::Eof, Symbol::intern(""), None, self.line, Span { start: end, end }));
+
+        if self.had_error {
+            Err(LoxScanError)
+        } else {
+            Ok(self.tokens)
+        }
     }
 
     fn is_at_end(&self) -> bool {
@@ -114,14 +137,18 @@ impl<'a> Scanner<'a> {
                     self.add_token(TokenType::Slash, None);
                 }
             }
-            b' ' | b'\r' | b'\t' => {}
-            b'\n' => self.line += 1,
+            b' ' | b'\r' | b'\t' | b'\n' => {}
             b'"' => self.string(),
             n if Scanner::is_numeric(n) => self.number(),
             n if Scanner::is_alpha(n) => self.identifier(),
             _ => {
-                let error = Lox::error(self.line, String::from("Unexpected character."));
-                error.report(&self.current.to_string());
+                // Take the rest of a multi-byte character, so it is reported once.
+                while self.peek() & 0xC0 == 0x80 {
+                    self.advance();
+                }
+                let span = Span { start: self.start_location, end: self.location() };
+                Diagnostic::at(ErrorCode::UnexpectedCharacter, "Unexpected character.", span).report(self.reporter);
+                self.had_error = true;
             }
         }
     }
@@ -139,7 +166,7 @@ impl<'a> Scanner<'a> {
             return false;
         }
 
-        self.current += 1;
+        self.advance();
         true
     }
 
@@ -174,25 +201,39 @@ impl<'a> Scanner<'a> {
     }
 
     fn advance(&mut self) -> u8 {
-        let ch = self
+        let ch = *self
             .source
             .get(self.current)
             .expect("Error: Scanner advance: index out of bounds");
         self.current += 1;
-        *ch
+
+        if ch == b'\n' {
+            self.line += 1;
+            self.column = 1;
+        } else if ch & 0xC0 != 0x80 {
+            // Only bytes that start a UTF-8 character move the column.
+            self.column += 1;
+        }
+        ch
+    }
+
+    fn location(&self) -> Location {
+        Location { offset: self.current, line: self.line, column: self.column }
     }
 
     fn string(&mut self) {
         while self.peek() != b'"' && !self.is_at_end() {
-            if self.peek() == b'\n' {
-                self.line += 1;
-            }
             self.advance();
         }
 
         if self.is_at_end() {
-            let error = Lox::error(self.line - 1, String::from("Unterminated string."));
-            error.report(&self.current.to_string());
+            let start = self.start_location;
+            let quote = Location { offset: start.offset + 1, column: start.column + 1, ..start };
+            Diagnostic::at(ErrorCode::UnterminatedString, "Unterminated string.", Span { start, end: quote })
+                .with_label("string starts here")
+                .with_help("close the string with a '\"'")
+                .report(self.reporter);
+            self.had_error = true;
             return;
         }
 
@@ -200,15 +241,16 @@ impl<'a> Scanner<'a> {
 
         let byte_slice = &self.source[(self.start + 1)..(self.current - 1)];
         let value_str = std::str::from_utf8(byte_slice).expect("Couldn't read string.");
-        self.add_token(TokenType::String, Some(Object::Str(value_str.to_string())));
+        self.add_token(TokenType::String, Some(Object::Str(Symbol::intern(value_str))));
     }
 
     fn add_token(&mut self, ttype: TokenType, literal: Option<Object>) {
         let text = self.source.get(self.start..self.current);
         if let Some(text) = text {
             let text_str = std::str::from_utf8(text).unwrap();
+            let span = Span { start: self.start_location, end: self.location() };
             self.tokens
-                .push(Token::new(ttype, text_str.to_string(), literal, self.line));
+                .push(Token::new(ttype, Symbol::intern(text_str), literal, self.line, span));
         } else {
             self.current = self.start;
         }
@@ -281,3 +323,51 @@ impl<'a> Scanner<'a> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tokens_carry_byte_ranges_and_character_columns() {
+        let source = "print \"é\"; x\n  \"two\nlines\" y";
+        let tokens = Scanner::new(source.as_bytes(), &Reporter::default()).scan_tokens().expect("test source should scan");
+        let spans: Vec<_> = tokens.iter().map(|token| token.span).collect();
+
+        // `x` sits after a two-byte character, so its column trails its offset.
+        assert_eq!(&source[spans[3].start.offset..spans[3].end.offset], "x");
+        assert_eq!(spans[3].start, Location { offset: 12, line: 1, column: 12 });
+
+        let string = spans[4];
+        assert_eq!(&source[string.start.offset..string.end.offset], "\"two\nlines\"");
+        assert_eq!((string.start.line, string.start.column), (2, 3));
+        assert_eq!((string.end.line, string.end.column), (3, 7));
+
+        assert_eq!(spans[5].start, Location { offset: 28, line: 3, column: 8 });
+        assert_eq!(tokens[6].ttype, TokenType::Eof);
+    }
+
+    #[test]
+    fn a_multi_byte_character_is_one_unexpected_character() {
+        let source = "a é b";
+        let reporter = Reporter::default();
+        let mut scanner = Scanner::new(source.as_bytes(), &reporter);
+        // Scan token by token, keeping the span of each one that errs.
+        let mut spans = Vec::new();
+        while !scanner.is_at_end() {
+            scanner.start = scanner.current;
+            scanner.start_location = scanner.location();
+            let errors = scanner.had_error;
+            scanner.scan_token();
+            if scanner.had_error && !errors {
+                spans.push(Span { start: scanner.start_location, end: scanner.location() });
+                scanner.had_error = false;
+            }
+        }
+
+        assert_eq!(spans.len(), 1);
+        assert_eq!((spans[0].start.column, spans[0].end.column), (3, 4));
+        assert_eq!(&source[spans[0].start.offset..spans[0].end.offset], "é");
+        assert_eq!(scanner.tokens[1].span.start.column, 5);
+    }
+}
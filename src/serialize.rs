@@ -0,0 +1,700 @@
+//! Binary format for compiled scripts, so they can be compiled once and run
+//! later without the source.
+//!
+//! ```text
+//! file     := MAGIC version:u16 length:u32 checksum:u32 function
+//! function := name arity:u32 upvalues:u32 code lines constants
+//! name     := 0 | 1 string
+//! code     := len:u32 byte*
+//! lines    := runs:u32 (line:u32 count:u32)*
+//! constants:= len:u32 constant*
+//! constant := 0 f64 | 1 string | 2 function
+//! string   := len:u32 utf8-byte*
+//! ```
+//!
+//! Integers are little-endian. `length` and `checksum` (FNV-1a) cover
+//! everything after the header. Loading also verifies that every instruction
+//! is well formed and keeps the value stack within its frame, and that the
+//! script itself can be called without arguments or captures, so a damaged
+//! file is rejected before the VM runs any of it.
+
+use std::rc::Rc;
+
+use crate::chunk::{Chunk, Constant, Function, OpCode};
+use crate::symbol::Symbol;
+
+pub const MAGIC: [u8; 4] = *b"\x7fLOX";
+pub const FORMAT_VERSION: u16 = 1;
+
+const HEADER_LEN: usize = MAGIC.len() + 2 + 4 + 4;
+/// Guards the loader's recursion against maliciously deep nesting.
+const MAX_NESTING: usize = 256;
+
+const TAG_NUMBER: u8 = 0;
+const TAG_STRING: u8 = 1;
+const TAG_FUNCTION: u8 = 2;
+
+#[derive(Debug)]
+pub struct LoadError {
+    message: String,
+}
+
+impl LoadError {
+    fn new(message: impl Into<String>) -> Self {
+        LoadError { message: message.into() }
+    }
+}
+
+impl std::fmt::Display for LoadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+pub fn is_bytecode(bytes: &[u8]) -> bool {
+    bytes.starts_with(&MAGIC)
+}
+
+pub fn serialize(function: &Function) -> Vec<u8> {
+    let mut payload = Vec::new();
+    write_function(&mut payload, function);
+
+    let mut bytes = Vec::with_capacity(HEADER_LEN + payload.len());
+    bytes.extend_from_slice(&MAGIC);
+    bytes.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+    write_u32(&mut bytes, payload.len());
+    bytes.extend_from_slice(&checksum(&payload).to_le_bytes());
+    bytes.extend_from_slice(&payload);
+    bytes
+}
+
+pub fn deserialize(bytes: &[u8]) -> Result<Rc<Function>, LoadError> {
+    if !is_bytecode(bytes) {
+        return Err(LoadError::new("Not an rlox bytecode file."));
+    }
+    if bytes.len() < HEADER_LEN {
+        return Err(LoadError::new("Truncated bytecode file header."));
+    }
+
+    let version = u16::from_le_bytes([bytes[4], bytes[5]]);
+    if version != FORMAT_VERSION {
+        return Err(LoadError::new(format!(
+            "Unsupported bytecode version {version}, this rlox reads version {FORMAT_VERSION}."
+        )));
+    }
+
+    let length = u32::from_le_bytes([bytes[6], bytes[7], bytes[8], bytes[9]]) as usize;
+    let expected = u32::from_le_bytes([bytes[10], bytes[11], bytes[12], bytes[13]]);
+    let payload = &bytes[HEADER_LEN..];
+    if payload.len() < length {
+        return Err(LoadError::new(format!(
+            "Truncated bytecode file: expected {length} bytes of code, found {}.",
+            payload.len()
+        )));
+    }
+    if payload.len() > length {
+        return Err(LoadError::new("Unexpected trailing bytes after the script."));
+    }
+    if checksum(payload) != expected {
+        return Err(LoadError::new("Bytecode file is corrupted (checksum mismatch)."));
+    }
+
+    let mut reader = Reader { bytes: payload, position: 0 };
+    let function = reader.function(0)?;
+    if reader.position != payload.len() {
+        return Err(LoadError::new("Unexpected trailing bytes after the script."));
+    }
+    // The VM calls the script with no arguments and nothing to capture from.
+    if function.arity != 0 || function.upvalue_count != 0 {
+        return Err(LoadError::new("The script takes no parameters and captures no variables."));
+    }
+
+    Ok(Rc::new(function))
+}
+
+fn write_function(out: &mut Vec<u8>, function: &Function) {
+    match &function.name {
+        Some(name) => {
+            out.push(1);
+            write_string(out, name);
+        }
+        None => out.push(0),
+    }
+    write_u32(out, function.arity);
+    write_u32(out, function.upvalue_count);
+
+    let chunk = &function.chunk;
+    write_u32(out, chunk.code.len());
+    out.extend_from_slice(&chunk.code);
+
+    let runs = line_runs(&chunk.lines);
+    write_u32(out, runs.len());
+    for (line, count) in runs {
+        write_u32(out, line);
+        write_u32(out, count);
+    }
+
+    write_u32(out, chunk.constants.len());
+    for constant in &chunk.constants {
+        match constant {
+            Constant::Number(num) => {
+                out.push(TAG_NUMBER);
+                out.extend_from_slice(&num.to_le_bytes());
+            }
+            Constant::Str(s) => {
+                out.push(TAG_STRING);
+                write_string(out, s);
+            }
+            Constant::Function(nested) => {
+                out.push(TAG_FUNCTION);
+                write_function(out, nested);
+            }
+        }
+    }
+}
+
+/// Run-length encodes the per-byte line table as `(line, count)` pairs.
+fn line_runs(lines: &[usize]) -> Vec<(usize, usize)> {
+    let mut runs: Vec<(usize, usize)> = Vec::new();
+    for &line in lines {
+        match runs.last_mut() {
+            Some((last, count)) if *last == line => *count += 1,
+            _ => runs.push((line, 1)),
+        }
+    }
+    runs
+}
+
+fn write_u32(out: &mut Vec<u8>, value: usize) {
+    let value = u32::try_from(value).expect("Bytecode sizes fit in 32 bits.");
+    out.extend_from_slice(&value.to_le_bytes());
+}
+
+fn write_string(out: &mut Vec<u8>, s: &str) {
+    write_u32(out, s.len());
+    out.extend_from_slice(s.as_bytes());
+}
+
+fn checksum(bytes: &[u8]) -> u32 {
+    bytes.iter().fold(0x811c_9dc5, |hash, &byte| (hash ^ byte as u32).wrapping_mul(0x0100_0193))
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl Reader<'_> {
+    fn function(&mut self, depth: usize) -> Result<Function, LoadError> {
+        if depth > MAX_NESTING {
+            return Err(LoadError::new("Functions are nested too deeply."));
+        }
+
+        let name = match self.u8()? {
+            0 => None,
+            1 => Some(self.string()?),
+            tag => return Err(LoadError::new(format!("Invalid function name tag {tag}."))),
+        };
+        let arity = self.u32()?;
+        let upvalue_count = self.u32()?;
+
+        let code_len = self.u32()?;
+        let code = self.take(code_len)?.to_vec();
+
+        let mut lines = Vec::with_capacity(code_len);
+        for _ in 0..self.u32()? {
+            let line = self.u32()?;
+            let count = self.u32()?;
+            if lines.len() + count > code_len {
+                return Err(LoadError::new("Line table is longer than the code."));
+            }
+            lines.extend(std::iter::repeat_n(line, count));
+        }
+        if lines.len() != code_len {
+            return Err(LoadError::new("Line table does not cover the code."));
+        }
+
+        let constant_count = self.u32()?;
+        let mut constants = Vec::new();
+        for _ in 0..constant_count {
+            let constant = match self.u8()? {
+                TAG_NUMBER => Constant::Number(f64::from_le_bytes(self.array()?)),
+                TAG_STRING => Constant::Str(Symbol::intern(&self.string()?)),
+                TAG_FUNCTION => Constant::Function(Rc::new(self.function(depth + 1)?)),
+                tag => return Err(LoadError::new(format!("Invalid constant tag {tag}."))),
+            };
+            constants.push(constant);
+        }
+
+        let function = Function {
+            name,
+            arity,
+            upvalue_count,
+            chunk: Chunk { code, constants, lines },
+        };
+        verify(&function)?;
+        Ok(function)
+    }
+
+    fn take(&mut self, len: usize) -> Result<&[u8], LoadError> {
+        let end = self.position.checked_add(len).filter(|&end| end <= self.bytes.len());
+        let Some(end) = end else {
+            return Err(LoadError::new("Unexpected end of bytecode file."));
+        };
+
+        let slice = &self.bytes[self.position..end];
+        self.position = end;
+        Ok(slice)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], LoadError> {
+        let mut array = [0; N];
+        array.copy_from_slice(self.take(N)?);
+        Ok(array)
+    }
+
+    fn u8(&mut self) -> Result<u8, LoadError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<usize, LoadError> {
+        Ok(u32::from_le_bytes(self.array()?) as usize)
+    }
+
+    fn string(&mut self) -> Result<String, LoadError> {
+        let len = self.u32()?;
+        let bytes = self.take(len)?.to_vec();
+        String::from_utf8(bytes).map_err(|_| LoadError::new("String constant is not valid UTF-8."))
+    }
+}
+
+/// Checks that the code decodes into whole instructions whose operands refer
+/// to existing constants, upvalues and instruction boundaries, and that it
+/// ends in a return, so the VM can't run off the end of the chunk.
+fn verify(function: &Function) -> Result<(), LoadError> {
+    let chunk = &function.chunk;
+    let code = &chunk.code;
+    let invalid = |offset: usize, reason: &str| {
+        LoadError::new(format!("Invalid bytecode in {function} at offset {offset}: {reason}."))
+    };
+
+    let mut boundaries = vec![false; code.len()];
+    let mut jumps = Vec::new();
+    let mut last = None;
+    let mut offset = 0;
+
+    while offset < code.len() {
+        boundaries[offset] = true;
+        let op = OpCode::from_byte(code[offset]).ok_or_else(|| invalid(offset, "unknown opcode"))?;
+        let operand = |len: usize| {
+            if offset + len < code.len() {
+                Ok(())
+            } else {
+                Err(invalid(offset, "truncated operand"))
+            }
+        };
+        let constant = |index: u16| {
+            chunk.constants.get(index as usize).ok_or_else(|| invalid(offset, "constant out of range"))
+        };
+
+        let next = match op {
+            OpCode::Constant => {
+                operand(2)?;
+                match constant(chunk.read_u16(offset + 1))? {
+                    Constant::Number(_) | Constant::Str(_) => {}
+                    Constant::Function(_) => return Err(invalid(offset, "function loaded as a value")),
+                }
+                offset + 3
+            }
+            OpCode::GetGlobal
+            | OpCode::DefineGlobal
+            | OpCode::SetGlobal
+            | OpCode::GetProperty
+            | OpCode::SetProperty
+            | OpCode::GetSuper
+            | OpCode::Class
+            | OpCode::Method => {
+                operand(2)?;
+                if !matches!(constant(chunk.read_u16(offset + 1))?, Constant::Str(_)) {
+                    return Err(invalid(offset, "name is not a string"));
+                }
+                offset + 3
+            }
+            OpCode::GetLocal | OpCode::SetLocal | OpCode::Call => {
+                operand(1)?;
+                offset + 2
+            }
+            OpCode::GetUpvalue | OpCode::SetUpvalue => {
+                operand(1)?;
+                if code[offset + 1] as usize >= function.upvalue_count {
+                    return Err(invalid(offset, "upvalue out of range"));
+                }
+                offset + 2
+            }
+            OpCode::Jump | OpCode::JumpIfFalse | OpCode::Loop => {
+                operand(2)?;
+                let distance = chunk.read_u16(offset + 1) as usize;
+                let target = if op == OpCode::Loop {
+                    (offset + 3).checked_sub(distance)
+                } else {
+                    Some(offset + 3 + distance)
+                };
+                match target {
+                    Some(target) if target < code.len() => jumps.push((offset, target)),
+                    _ => return Err(invalid(offset, "jump out of range")),
+                }
+                offset + 3
+            }
+            OpCode::Closure => {
+                operand(2)?;
+                let Constant::Function(nested) = constant(chunk.read_u16(offset + 1))? else {
+                    return Err(invalid(offset, "closure of a non-function"));
+                };
+
+                let mut next = offset + 3;
+                for _ in 0..nested.upvalue_count {
+                    if next + 1 >= code.len() {
+                        return Err(invalid(offset, "truncated upvalue list"));
+                    }
+                    match code[next] {
+                        1 => {}
+                        0 if (code[next + 1] as usize) < function.upvalue_count => {}
+                        _ => return Err(invalid(offset, "invalid upvalue capture")),
+                    }
+                    next += 2;
+                }
+                next
+            }
+            _ => offset + 1,
+        };
+
+        last = Some(op);
+        offset = next;
+    }
+
+    if last != Some(OpCode::Return) {
+        return Err(invalid(code.len(), "code does not end in a return"));
+    }
+
+    if let Some((offset, _)) = jumps.into_iter().find(|&(_, target)| !boundaries[target]) {
+        return Err(invalid(offset, "jump into the middle of an instruction"));
+    }
+
+    verify_stack(function).map_err(|(offset, reason)| invalid(offset, reason))
+}
+
+/// Follows every path through code that `verify` has already decoded,
+/// tracking the frame's stack slots and which of them closures have
+/// captured. Every path must reach an instruction with the same stack depth,
+/// no instruction may pop more than the frame holds or address a local above
+/// the top, and captured locals may only leave through `CloseUpvalue`.
+fn verify_stack(function: &Function) -> Result<(), (usize, &'static str)> {
+    let chunk = &function.chunk;
+    let code = &chunk.code;
+
+    // One flag per slot: whether a closure has captured it.
+    let mut seen: Vec<Option<Vec<bool>>> = vec![None; code.len()];
+    let mut pending = vec![(0, vec![false; function.arity + 1])];
+
+    while let Some((mut offset, mut stack)) = pending.pop() {
+        loop {
+            match &seen[offset] {
+                Some(previous) if previous.len() != stack.len() => {
+                    return Err((offset, "stack depth differs between paths"));
+                }
+                // A slot captured on any path, like a loop variable captured
+                // in the body, counts as captured from here on.
+                Some(previous) => {
+                    let merged: Vec<bool> = previous.iter().zip(&stack).map(|(a, b)| a | b).collect();
+                    if merged == *previous {
+                        break;
+                    }
+                    stack = merged;
+                }
+                None => {}
+            }
+            seen[offset] = Some(stack.clone());
+
+            let op = OpCode::from_byte(code[offset]).expect("Opcodes were decoded by verify.");
+            let fail = |reason| (offset, reason);
+            let need = |stack: &Vec<bool>, count: usize| {
+                if stack.len() >= count {
+                    Ok(())
+                } else {
+                    Err(fail("stack underflow"))
+                }
+            };
+            let pop = |stack: &mut Vec<bool>, count: usize| {
+                need(stack, count)?;
+                match stack.split_off(stack.len() - count).contains(&true) {
+                    true => Err(fail("captured local popped without closing it")),
+                    false => Ok(()),
+                }
+            };
+            let local = |stack: &Vec<bool>| {
+                if (code[offset + 1] as usize) < stack.len() {
+                    Ok(())
+                } else {
+                    Err(fail("local slot out of range"))
+                }
+            };
+
+            let next = match op {
+                OpCode::Constant | OpCode::GetGlobal | OpCode::Class => {
+                    stack.push(false);
+                    offset + 3
+                }
+                OpCode::Nil | OpCode::True | OpCode::False => {
+                    stack.push(false);
+                    offset + 1
+                }
+                OpCode::GetUpvalue => {
+                    stack.push(false);
+                    offset + 2
+                }
+                OpCode::Pop | OpCode::Print => {
+                    pop(&mut stack, 1)?;
+                    offset + 1
+                }
+                OpCode::DefineGlobal => {
+                    pop(&mut stack, 1)?;
+                    offset + 3
+                }
+                OpCode::SetGlobal => {
+                    need(&stack, 1)?;
+                    offset + 3
+                }
+                OpCode::SetUpvalue => {
+                    need(&stack, 1)?;
+                    offset + 2
+                }
+                OpCode::GetLocal => {
+                    local(&stack)?;
+                    stack.push(false);
+                    offset + 2
+                }
+                OpCode::SetLocal => {
+                    local(&stack)?;
+                    offset + 2
+                }
+                OpCode::GetProperty => {
+                    pop(&mut stack, 1)?;
+                    stack.push(false);
+                    offset + 3
+                }
+                OpCode::SetProperty | OpCode::GetSuper => {
+                    pop(&mut stack, 2)?;
+                    stack.push(false);
+                    offset + 3
+                }
+                OpCode::Equal
+                | OpCode::Greater
+                | OpCode::GreaterEqual
+                | OpCode::Less
+                | OpCode::LessEqual
+                | OpCode::Add
+                | OpCode::Subtract
+                | OpCode::Multiply
+                | OpCode::Divide
+                | OpCode::Power => {
+                    pop(&mut stack, 2)?;
+                    stack.push(false);
+                    offset + 1
+                }
+                OpCode::Not | OpCode::Negate => {
+                    pop(&mut stack, 1)?;
+                    stack.push(false);
+                    offset + 1
+                }
+                OpCode::Jump => offset + 3 + chunk.read_u16(offset + 1) as usize,
+                OpCode::Loop => offset + 3 - chunk.read_u16(offset + 1) as usize,
+                OpCode::JumpIfFalse => {
+                    need(&stack, 1)?;
+                    pending.push((offset + 3 + chunk.read_u16(offset + 1) as usize, stack.clone()));
+                    offset + 3
+                }
+                OpCode::Call => {
+                    pop(&mut stack, code[offset + 1] as usize + 1)?;
+                    stack.push(false);
+                    offset + 2
+                }
+                OpCode::Closure => {
+                    let Constant::Function(nested) = &chunk.constants[chunk.read_u16(offset + 1) as usize] else {
+                        unreachable!("Closure operands were checked by verify.");
+                    };
+
+                    let mut next = offset + 3;
+                    for _ in 0..nested.upvalue_count {
+                        if code[next] == 1 {
+                            match stack.get_mut(code[next + 1] as usize) {
+                                Some(captured) => *captured = true,
+                                None => return Err(fail("captured local out of range")),
+                            }
+                        }
+                        next += 2;
+                    }
+                    stack.push(false);
+                    next
+                }
+                OpCode::CloseUpvalue => {
+                    need(&stack, 1)?;
+                    stack.pop();
+                    offset + 1
+                }
+                OpCode::Return => {
+                    pop(&mut stack, 1)?;
+                    break;
+                }
+                OpCode::Inherit | OpCode::Method => {
+                    need(&stack, 2)?;
+                    pop(&mut stack, 1)?;
+                    offset + if op == OpCode::Inherit { 1 } else { 3 }
+                }
+            };
+
+            offset = next;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::Reporter;
+    use crate::compiler::Compiler;
+    use crate::debug::disassemble_function;
+    use crate::parser::Parser;
+    use crate::scanner::Scanner;
+
+    fn compile(source: &str) -> Rc<Function> {
+        let tokens = Scanner::new(source.as_bytes(), &Reporter::default()).scan_tokens().expect("test program should scan");
+        let statements = Parser::new(tokens, &Reporter::default()).parse().expect("test program should parse");
+        match Compiler::new(&Reporter::default()).compile(&statements) {
+            Ok(function) => function,
+            Err(_) => panic!("test program should compile"),
+        }
+    }
+
+    const PROGRAM: &str = "
+        fun makeCounter() {
+            var i = 0;
+            fun count() { i = i + 1; return i; }
+            return count;
+        }
+        class Greeter { greet(name) { print \"hi \" + name; } }
+        var counter = makeCounter();
+        while (counter() < 3) Greeter().greet(\"there\");
+        print 2.5 ^ 2;
+    ";
+
+    #[test]
+    fn round_trips_nested_functions_constants_and_lines() {
+        let function = compile(PROGRAM);
+        let loaded = deserialize(&serialize(&function)).expect("serialized script should load");
+
+        assert_eq!(disassemble_function(&loaded), disassemble_function(&function));
+    }
+
+    #[test]
+    fn rejects_other_versions() {
+        let mut bytes = serialize(&compile(PROGRAM));
+        bytes[4..6].copy_from_slice(&(FORMAT_VERSION + 1).to_le_bytes());
+
+        let error = deserialize(&bytes).expect_err("a newer version should be rejected");
+        assert!(error.to_string().contains("Unsupported bytecode version"));
+    }
+
+    #[test]
+    fn rejects_every_truncation() {
+        let bytes = serialize(&compile(PROGRAM));
+
+        for len in 0..bytes.len() {
+            assert!(deserialize(&bytes[..len]).is_err(), "truncation to {len} bytes was accepted");
+        }
+    }
+
+    #[test]
+    fn reports_truncation_as_such() {
+        let bytes = serialize(&compile(PROGRAM));
+
+        let error = deserialize(&bytes[..bytes.len() - 1]).expect_err("truncated file should be rejected");
+        assert!(error.to_string().starts_with("Truncated bytecode file"));
+    }
+
+    #[test]
+    fn rejects_corrupted_bytes() {
+        let bytes = serialize(&compile(PROGRAM));
+
+        for index in 0..bytes.len() {
+            let mut corrupted = bytes.clone();
+            corrupted[index] ^= 0x5a;
+            assert!(deserialize(&corrupted).is_err(), "corruption at byte {index} was accepted");
+        }
+    }
+
+    #[test]
+    fn verifier_rejects_malformed_code_even_with_a_valid_checksum() {
+        let mut function = Function::default();
+        function.chunk.write(OpCode::Constant as u8, 1);
+        function.chunk.write(0, 1);
+        function.chunk.write(7, 1);
+        function.chunk.write(OpCode::Return as u8, 1);
+
+        let error = deserialize(&serialize(&function)).expect_err("dangling constant should be rejected");
+        assert!(error.to_string().contains("constant out of range"));
+    }
+
+    fn function(code: &[u8]) -> Function {
+        let mut function = Function::default();
+        for &byte in code {
+            function.chunk.write(byte, 1);
+        }
+        function
+    }
+
+    #[test]
+    fn verifier_rejects_stack_underflow() {
+        let function = function(&[OpCode::Pop as u8, OpCode::Pop as u8, OpCode::Return as u8]);
+
+        let error = deserialize(&serialize(&function)).expect_err("underflow should be rejected");
+        assert!(error.to_string().contains("stack underflow"));
+    }
+
+    #[test]
+    fn verifier_rejects_locals_above_the_stack() {
+        let function = function(&[OpCode::GetLocal as u8, 200, OpCode::Return as u8]);
+
+        let error = deserialize(&serialize(&function)).expect_err("out-of-range local should be rejected");
+        assert!(error.to_string().contains("local slot out of range"));
+    }
+
+    #[test]
+    fn verifier_rejects_popping_a_captured_local() {
+        let nested = Function { upvalue_count: 1, ..function(&[OpCode::Nil as u8, OpCode::Return as u8]) };
+
+        let mut script = function(&[OpCode::Nil as u8, OpCode::Closure as u8, 0, 0, 1, 1, OpCode::Pop as u8, OpCode::Pop as u8]);
+        script.chunk.write(OpCode::Return as u8, 1);
+        script.chunk.add_constant(Constant::Function(Rc::new(nested)));
+
+        let error = deserialize(&serialize(&script)).expect_err("dropping a captured local should be rejected");
+        assert!(error.to_string().contains("captured local popped without closing it"));
+    }
+
+    #[test]
+    fn rejects_a_script_with_parameters() {
+        let script = Function { arity: 1, ..function(&[OpCode::Nil as u8, OpCode::Return as u8]) };
+
+        let error = deserialize(&serialize(&script)).expect_err("a script with parameters should be rejected");
+        assert!(error.to_string().contains("takes no parameters"));
+    }
+
+    #[test]
+    fn rejects_a_script_with_upvalues() {
+        let script = Function { upvalue_count: 1, ..function(&[OpCode::GetUpvalue as u8, 0, OpCode::Return as u8]) };
+
+        let error = deserialize(&serialize(&script)).expect_err("a script with upvalues should be rejected");
+        assert!(error.to_string().contains("captures no variables"));
+    }
+}
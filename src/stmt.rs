@@ -0,0 +1,39 @@
+use std::rc::Rc;
+
+use crate::expr::{define_ast, Expr, VariableExpr};
+use crate::token::Token;
+
+#[derive(Debug)]
+pub enum Stmt {
+    Expression(ExpressionStmt),
+    Print(PrintStmt),
+    Var(VarStmt),
+    Block(BlockStmt),
+    If(IfStmt),
+    While(WhileStmt),
+    Function(FunctionStmt),
+    Return(ReturnStmt),
+    Class(ClassStmt),
+}
+
+pub trait Visitor<T> {
+    fn visit_expression(&self, stmt: &ExpressionStmt) -> T;
+    fn visit_print(&self, stmt: &PrintStmt) -> T;
+    fn visit_var(&self, stmt: &VarStmt) -> T;
+    fn visit_block(&self, stmt: &BlockStmt) -> T;
+    fn visit_if(&self, stmt: &IfStmt) -> T;
+    fn visit_while(&self, stmt: &WhileStmt) -> T;
+    fn visit_function(&self, stmt: &FunctionStmt) -> T;
+    fn visit_return(&self, stmt: &ReturnStmt) -> T;
+    fn visit_class(&self, stmt: &ClassStmt) -> T;
+}
+
+define_ast!(ExpressionStmt, visit_expression : expr: Expr
+    ;PrintStmt, visit_print : expr: Expr
+    ;VarStmt, visit_var : name: Token, initializer: Option<Expr>
+    ;BlockStmt, visit_block : statements: Vec<Stmt>
+    ;IfStmt, visit_if : condition: Expr, then_branch: Box<Stmt>, else_branch: Option<Box<Stmt>>
+    ;WhileStmt, visit_while : condition: Expr, body: Box<Stmt>
+    ;FunctionStmt, visit_function : name: Token, params: Vec<Token>, body: Rc<Vec<Stmt>>
+    ;ReturnStmt, visit_return : keyword: Token, value: Option<Expr>
+    ;ClassStmt, visit_class : name: Token, superclass: Option<VariableExpr>, methods: Vec<FunctionStmt>);
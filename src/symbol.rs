@@ -0,0 +1,125 @@
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::hash::{Hash, Hasher};
+use std::ops::Deref;
+use std::rc::Rc;
+
+/// An interned string. Equal strings share one allocation, so comparing and
+/// hashing a symbol only looks at the pointer, never at the text.
+#[derive(Clone)]
+pub struct Symbol(Rc<str>);
+
+/// Table sizes below this are never purged.
+const MIN_PURGE: usize = 1024;
+
+struct Interner {
+    strings: HashSet<Rc<str>>,
+    /// Size at which strings no longer used outside the table are dropped.
+    next_purge: usize,
+}
+
+thread_local! {
+    static INTERNER: RefCell<Interner> = RefCell::new(Interner {
+        strings: HashSet::new(),
+        next_purge: MIN_PURGE,
+    });
+}
+
+impl Symbol {
+    pub fn intern(text: &str) -> Symbol {
+        INTERNER.with(|interner| {
+            let mut interner = interner.borrow_mut();
+            if let Some(existing) = interner.strings.get(text) {
+                return Symbol(Rc::clone(existing));
+            }
+
+            // Strings built at runtime, like concatenation results, would
+            // otherwise pile up here for the rest of the run.
+            if interner.strings.len() >= interner.next_purge {
+                interner.strings.retain(|string| Rc::strong_count(string) > 1);
+                interner.next_purge = (interner.strings.len() * 2).max(MIN_PURGE);
+            }
+
+            let string: Rc<str> = Rc::from(text);
+            interner.strings.insert(Rc::clone(&string));
+            Symbol(string)
+        })
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Deref for Symbol {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl PartialEq for Symbol {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for Symbol {}
+
+impl PartialEq<str> for Symbol {
+    fn eq(&self, other: &str) -> bool {
+        self.as_str() == other
+    }
+}
+
+impl PartialEq<&str> for Symbol {
+    fn eq(&self, other: &&str) -> bool {
+        self.as_str() == *other
+    }
+}
+
+impl Hash for Symbol {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        Rc::as_ptr(&self.0).cast::<u8>().hash(state);
+    }
+}
+
+impl std::fmt::Debug for Symbol {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self.as_str())
+    }
+}
+
+impl std::fmt::Display for Symbol {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn equal_strings_share_one_symbol() {
+        let built = format!("{}{}", "hel", "lo");
+        let a = Symbol::intern("hello");
+        let b = Symbol::intern(&built);
+
+        assert!(Rc::ptr_eq(&a.0, &b.0));
+        assert_eq!(a, b);
+        assert_ne!(a, Symbol::intern("world"));
+    }
+
+    #[test]
+    fn purging_keeps_symbols_still_in_use() {
+        let kept = Symbol::intern("kept across purges");
+        for i in 0..4 * MIN_PURGE {
+            Symbol::intern(&i.to_string());
+        }
+
+        assert_eq!(Symbol::intern("kept across purges"), kept);
+        INTERNER.with(|interner| assert!(interner.borrow().strings.len() < 4 * MIN_PURGE));
+    }
+}
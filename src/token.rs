@@ -1,11 +1,24 @@
+use std::rc::Rc;
+
+use crate::callable::{LoxFunction, NativeFunction};
+use crate::class::{LoxClass, LoxInstance};
+use crate::heap::{Trace, Tracer};
+use crate::symbol::Symbol;
 use crate::token_type::TokenType;
+use crate::userdata::UserData;
 
 #[derive(Debug, Clone)]
 pub enum Object {
     Num(f64),
-    Str(String),
+    Str(Symbol),
     Boolean(bool),
     Nil,
+    Function(Rc<LoxFunction>),
+    Native(Rc<NativeFunction>),
+    Class(Rc<LoxClass>),
+    Instance(LoxInstance),
+    /// A value owned by the host program.
+    UserData(Rc<dyn UserData>),
 }
 
 impl std::fmt::Display for Object {
@@ -22,29 +35,73 @@ impl std::fmt::Display for Object {
                 }
                 str
             },
-            Object::Str(s) => s.clone(),
+            Object::Str(s) => s.to_string(),
             Object::Boolean(b) => b.to_string(),
             Object::Nil => String::from("nil"),
+            Object::Function(function) => function.to_string(),
+            Object::Native(native) => native.to_string(),
+            Object::Class(class) => class.to_string(),
+            Object::Instance(instance) => instance.to_string(),
+            Object::UserData(data) => data.to_string(),
         };
         write!(f, "{str}")
     }
 }
 
+impl Trace for Object {
+    fn trace(&self, tracer: &mut Tracer) {
+        match self {
+            Object::Function(function) => function.trace(tracer),
+            Object::Class(class) => class.trace(tracer),
+            Object::Instance(instance) => instance.trace(tracer),
+            Object::Native(native) => native.trace(tracer),
+            Object::UserData(data) => data.trace(tracer),
+            Object::Num(_) | Object::Str(_) | Object::Boolean(_) | Object::Nil => {}
+        }
+    }
+}
+
+/// A point in the source: a byte offset plus the 1-based line and column it
+/// falls on. Columns count characters, not bytes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Location {
+    pub offset: usize,
+    pub line: usize,
+    pub column: usize,
+}
+
+/// The source range `start..end` covered by a token or expression. `end` is
+/// exclusive and may lie on a later line than `start`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Span {
+    pub start: Location,
+    pub end: Location,
+}
+
+impl Span {
+    /// The span from the start of `self` to the end of `other`.
+    pub fn to(self, other: Span) -> Span {
+        Span { start: self.start, end: other.end }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Token {
     pub ttype: TokenType,
-    pub lexeme: String,
+    pub lexeme: Symbol,
     pub literal: Option<Object>,
     pub line: usize,
+    pub span: Span,
 }
 
 impl Token {
-    pub fn new(ttype: TokenType, lexeme: String, literal: Option<Object>, line: usize) -> Self {
+    pub fn new(ttype: TokenType, lexeme: Symbol, literal: Option<Object>, line: usize, span: Span) -> Self {
         Self {
             ttype,
             lexeme,
             literal,
             line,
+            span,
         }
     }
 }
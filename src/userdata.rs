@@ -0,0 +1,86 @@
+use std::rc::Rc;
+
+use crate::callable::{Arity, NativeFunction};
+use crate::error::ErrorCode;
+use crate::heap::Tracer;
+use crate::interpreter::RuntimeError;
+use crate::token::{Object, Token};
+
+/// A Rust value handed to scripts as an opaque object, such as a database
+/// connection or a request context. Scripts only reach it through these
+/// hooks; a type that leaves one out has no fields, rejects assignments or
+/// has no methods. The hooks take `&self`, so state that scripts can change
+/// belongs in a `Cell` or `RefCell`.
+///
+/// Userdata that stores Lox values must report them from `trace`, or the
+/// collector may free them while it still holds them.
+///
+/// Like natives, userdata only reaches scripts on the tree-walking
+/// interpreter; the bytecode VM has no way to call its hooks.
+pub trait UserData: std::fmt::Display {
+    /// Names the type in error messages.
+    fn type_name(&self) -> &str;
+
+    /// Reads the field `name`, if there is one.
+    fn get(&self, _name: &str) -> Option<Object> {
+        None
+    }
+
+    /// Writes the field `name`. An `Err` becomes a runtime error.
+    fn set(&self, _name: &str, _value: Object) -> Result<(), String> {
+        Err(format!("Can't set properties on {}.", self.type_name()))
+    }
+
+    /// The arity of the method `name`, if there is one.
+    fn method(&self, _name: &str) -> Option<Arity> {
+        None
+    }
+
+    /// Runs a method that `method` reported. An `Err` becomes a runtime error.
+    fn call(&self, name: &str, _arguments: &[Object]) -> Result<Object, String> {
+        Err(format!("{} has no method '{name}'.", self.type_name()))
+    }
+
+    /// Passes every Lox value this holds to `tracer.value` during a
+    /// collection.
+    fn trace(&self, _tracer: &mut Tracer) {}
+}
+
+impl dyn UserData {
+    /// Looks `name` up as a field, then as a method, which comes back bound
+    /// to this value as a native function.
+    pub fn property(self: &Rc<Self>, name: &Token) -> Result<Object, RuntimeError> {
+        if let Some(value) = self.get(&name.lexeme) {
+            return Ok(value);
+        }
+
+        let Some(arity) = self.method(&name.lexeme) else {
+            return Err(RuntimeError::new(
+                ErrorCode::UndefinedProperty,
+                format!("Undefined property '{}'.", name.lexeme),
+                name.clone(),
+            ));
+        };
+
+        let receiver = Rc::clone(self);
+        let method = name.lexeme.clone();
+        let bound = NativeFunction::bound(
+            name.lexeme.clone(),
+            arity,
+            Object::UserData(Rc::clone(self)),
+            Box::new(move |arguments| receiver.call(&method, arguments)),
+        );
+        Ok(Object::Native(Rc::new(bound)))
+    }
+
+    pub fn set_property(&self, name: &Token, value: Object) -> Result<(), RuntimeError> {
+        self.set(&name.lexeme, value)
+            .map_err(|message| RuntimeError::new(ErrorCode::NativeError, message, name.clone()))
+    }
+}
+
+impl std::fmt::Debug for dyn UserData {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{self}")
+    }
+}
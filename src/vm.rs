@@ -0,0 +1,712 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use crate::chunk::{Constant, Function, OpCode};
+use crate::debug;
+use crate::error::{ErrorCode, Lox, LoxRuntimeError, Reporter, TraceFrame};
+use crate::heap::{Gc, GcConfig, Heap, Managed, Trace, Tracer};
+use crate::symbol::Symbol;
+
+/// Deepest call stack either backend allows, counting top-level code.
+pub const FRAMES_MAX: usize = 1024;
+
+#[derive(Debug, Clone)]
+pub enum Value {
+    Nil,
+    Bool(bool),
+    Num(f64),
+    Str(Symbol),
+    Closure(Rc<Closure>),
+    Class(Rc<RefCell<Class>>),
+    Instance(Instance),
+    BoundMethod(Rc<BoundMethod>),
+}
+
+impl std::fmt::Display for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Value::Nil => write!(f, "nil"),
+            Value::Bool(b) => write!(f, "{b}"),
+            Value::Num(num) => write!(f, "{num}"),
+            Value::Str(s) => write!(f, "{s}"),
+            Value::Closure(closure) => write!(f, "{}", closure.function),
+            Value::Class(class) => write!(f, "<class {}>", class.borrow().name),
+            Value::Instance(instance) => write!(f, "<{} instance>", instance.class.borrow().name),
+            Value::BoundMethod(bound) => write!(f, "{}", bound.method.function),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Closure {
+    function: Rc<Function>,
+    upvalues: Vec<Gc<Upvalue>>,
+}
+
+/// A captured variable. It points into the stack while the variable's scope
+/// is live and owns the value once that scope has been exited. Closures
+/// stored in captured variables can form cycles, so upvalues live on the heap.
+#[derive(Debug)]
+pub enum Upvalue {
+    Open(usize),
+    Closed(Value),
+}
+
+#[derive(Debug)]
+pub struct Class {
+    name: Symbol,
+    methods: HashMap<Symbol, Rc<Closure>>,
+}
+
+/// A reference to an instance. As on the tree backend, the class is kept in
+/// the reference and the mutable fields live on the heap.
+#[derive(Debug, Clone)]
+pub struct Instance {
+    class: Rc<RefCell<Class>>,
+    fields: Gc<Fields>,
+}
+
+#[derive(Debug, Default)]
+pub struct Fields {
+    values: HashMap<Symbol, Value>,
+}
+
+#[derive(Debug)]
+pub struct BoundMethod {
+    receiver: Value,
+    method: Rc<Closure>,
+}
+
+impl Trace for Value {
+    fn trace(&self, tracer: &mut Tracer) {
+        match self {
+            Value::Closure(closure) => closure.trace(tracer),
+            Value::Class(class) => class.borrow().trace(tracer),
+            Value::Instance(instance) => {
+                instance.class.borrow().trace(tracer);
+                tracer.mark(instance.fields);
+            }
+            Value::BoundMethod(bound) => {
+                bound.receiver.trace(tracer);
+                bound.method.trace(tracer);
+            }
+            Value::Nil | Value::Bool(_) | Value::Num(_) | Value::Str(_) => {}
+        }
+    }
+}
+
+impl Trace for Closure {
+    fn trace(&self, tracer: &mut Tracer) {
+        self.upvalues.iter().for_each(|&upvalue| tracer.mark(upvalue));
+    }
+}
+
+impl Trace for Upvalue {
+    fn trace(&self, tracer: &mut Tracer) {
+        // An open upvalue's variable is on the stack, which is a root anyway.
+        if let Upvalue::Closed(value) = self {
+            value.trace(tracer);
+        }
+    }
+}
+
+impl Trace for Class {
+    fn trace(&self, tracer: &mut Tracer) {
+        self.methods.values().for_each(|method| method.trace(tracer));
+    }
+}
+
+impl Trace for Fields {
+    fn trace(&self, tracer: &mut Tracer) {
+        self.values.values().for_each(|value| value.trace(tracer));
+    }
+}
+
+struct CallFrame {
+    closure: Rc<Closure>,
+    ip: usize,
+    /// Index of the frame's slot zero on the value stack.
+    slots: usize,
+}
+
+struct VmError {
+    code: ErrorCode,
+    message: String,
+    line: usize,
+}
+
+/// Stack-based virtual machine running bytecode produced by the `Compiler`.
+pub struct Vm {
+    stack: Vec<Value>,
+    frames: Vec<CallFrame>,
+    globals: HashMap<Symbol, Value>,
+    /// Upvalues still pointing into the stack, ordered by slot.
+    open_upvalues: Vec<Gc<Upvalue>>,
+    heap: Heap,
+    /// Print the value stack and the next instruction before executing it.
+    trace: bool,
+}
+
+impl Default for Vm {
+    fn default() -> Self {
+        Vm::new()
+    }
+}
+
+impl Vm {
+    pub fn new() -> Self {
+        Vm::with_gc_config(GcConfig::default())
+    }
+
+    pub fn with_gc_config(config: GcConfig) -> Self {
+        Vm {
+            stack: Vec::new(),
+            frames: Vec::new(),
+            globals: HashMap::new(),
+            open_upvalues: Vec::new(),
+            heap: Heap::new(config),
+            trace: false,
+        }
+    }
+
+    pub fn set_trace(&mut self, trace: bool) {
+        self.trace = trace;
+    }
+
+    /// Runs a compiled script, reporting a runtime error through `reporter`.
+    pub fn interpret(&mut self, function: Rc<Function>, reporter: &Reporter) -> Result<(), LoxRuntimeError> {
+        let closure = Rc::new(Closure { function, upvalues: Vec::new() });
+        self.stack.push(Value::Closure(Rc::clone(&closure)));
+
+        let result = self.call(closure, 0).and_then(|()| self.run());
+
+        if let Err(error) = result {
+            Lox::runtime_error_at(error.code, error.line, &error.message).with_trace(self.trace()).report(reporter);
+            self.stack.clear();
+            self.frames.clear();
+            self.open_upvalues.clear();
+            return Err(LoxRuntimeError);
+        }
+        Ok(())
+    }
+
+    fn run(&mut self) -> Result<(), VmError> {
+        loop {
+            if self.trace {
+                self.trace_instruction();
+            }
+
+            let byte = self.read_byte();
+            let Some(op) = OpCode::from_byte(byte) else {
+                return Err(self.error(ErrorCode::InvalidBytecode, format!("Unknown opcode {byte}.")));
+            };
+
+            match op {
+                OpCode::Constant => {
+                    let value = match self.read_constant() {
+                        Constant::Number(num) => Value::Num(num),
+                        Constant::Str(s) => Value::Str(s),
+                        Constant::Function(_) => unreachable!("Functions are loaded with OpCode::Closure."),
+                    };
+                    self.stack.push(value);
+                }
+                OpCode::Nil => self.stack.push(Value::Nil),
+                OpCode::True => self.stack.push(Value::Bool(true)),
+                OpCode::False => self.stack.push(Value::Bool(false)),
+                OpCode::Pop => {
+                    self.pop();
+                }
+                OpCode::GetLocal => {
+                    let slot = self.frame().slots + self.read_byte() as usize;
+                    self.stack.push(self.stack[slot].clone());
+                }
+                OpCode::SetLocal => {
+                    let slot = self.frame().slots + self.read_byte() as usize;
+                    self.stack[slot] = self.peek(0).clone();
+                }
+                OpCode::GetGlobal => {
+                    let name = self.read_string();
+                    match self.globals.get(&name) {
+                        Some(value) => self.stack.push(value.clone()),
+                        None => return Err(self.error(ErrorCode::UndefinedVariable, format!("Undefined variable '{name}'."))),
+                    }
+                }
+                OpCode::DefineGlobal => {
+                    let name = self.read_string();
+                    let value = self.pop();
+                    self.globals.insert(name, value);
+                }
+                OpCode::SetGlobal => {
+                    let name = self.read_string();
+                    if !self.globals.contains_key(&name) {
+                        return Err(self.error(ErrorCode::UndefinedVariable, format!("Undefined variable '{name}'.")));
+                    }
+                    self.globals.insert(name, self.peek(0).clone());
+                }
+                OpCode::GetUpvalue => {
+                    let index = self.read_byte() as usize;
+                    let upvalue = self.frame().closure.upvalues[index];
+                    let value = match self.heap.get(upvalue) {
+                        Upvalue::Open(slot) => self.stack[*slot].clone(),
+                        Upvalue::Closed(value) => value.clone(),
+                    };
+                    self.stack.push(value);
+                }
+                OpCode::SetUpvalue => {
+                    let index = self.read_byte() as usize;
+                    let upvalue = self.frame().closure.upvalues[index];
+                    let value = self.peek(0).clone();
+                    match self.heap.get_mut(upvalue) {
+                        Upvalue::Open(slot) => self.stack[*slot] = value,
+                        Upvalue::Closed(closed) => *closed = value,
+                    }
+                }
+                OpCode::GetProperty => {
+                    let name = self.read_string();
+                    let Value::Instance(instance) = self.peek(0).clone() else {
+                        return Err(self.error(ErrorCode::NotAnInstance, String::from("Only instances have properties.")));
+                    };
+
+                    let field = self.heap.get(instance.fields).values.get(&name).cloned();
+                    match field {
+                        Some(value) => {
+                            self.pop();
+                            self.stack.push(value);
+                        }
+                        None => self.bind_method(&instance.class, &name)?,
+                    }
+                }
+                OpCode::SetProperty => {
+                    let name = self.read_string();
+                    let Value::Instance(instance) = self.peek(1).clone() else {
+                        return Err(self.error(ErrorCode::NotAnInstance, String::from("Only instances have fields.")));
+                    };
+
+                    let value = self.pop();
+                    self.heap.get_mut(instance.fields).values.insert(name, value.clone());
+                    self.pop();
+                    self.stack.push(value);
+                }
+                OpCode::GetSuper => {
+                    let name = self.read_string();
+                    let Value::Class(superclass) = self.pop() else {
+                        return Err(self.error(ErrorCode::InvalidBytecode, String::from("'super' is not bound to a class.")));
+                    };
+                    self.bind_method(&superclass, &name)?;
+                }
+                OpCode::Equal => {
+                    let b = self.pop();
+                    let a = self.pop();
+                    self.stack.push(Value::Bool(Vm::values_equal(&a, &b)));
+                }
+                OpCode::Greater => self.comparison(|a, b| a > b)?,
+                OpCode::GreaterEqual => self.comparison(|a, b| a >= b)?,
+                OpCode::Less => self.comparison(|a, b| a < b)?,
+                OpCode::LessEqual => self.comparison(|a, b| a <= b)?,
+                OpCode::Add => {
+                    let b = self.pop();
+                    let a = self.pop();
+                    match (a, b) {
+                        (Value::Num(a), Value::Num(b)) => self.stack.push(Value::Num(a + b)),
+                        (Value::Str(a), Value::Str(b)) => self.stack.push(Value::Str(Symbol::intern(&format!("{a}{b}")))),
+                        _ => return Err(self.error(ErrorCode::OperandsNotNumbersOrStrings, String::from("Operands must be two numbers or two strings."))),
+                    }
+                }
+                OpCode::Subtract => self.arithmetic(|a, b| a - b)?,
+                OpCode::Multiply => self.arithmetic(|a, b| a * b)?,
+                OpCode::Power => self.arithmetic(f64::powf)?,
+                OpCode::Divide => {
+                    if let (Value::Num(_), Value::Num(b)) = (self.peek(1), self.peek(0)) {
+                        if *b == 0.0 {
+                            return Err(self.error(ErrorCode::DivideByZero, String::from("Cannot divide by zero.")));
+                        }
+                    }
+                    self.arithmetic(|a, b| a / b)?
+                }
+                OpCode::Not => match self.pop() {
+                    Value::Bool(b) => self.stack.push(Value::Bool(!b)),
+                    _ => return Err(self.error(ErrorCode::OperandNotBoolean, String::from("Operand must be a boolean."))),
+                },
+                OpCode::Negate => match self.pop() {
+                    Value::Num(num) => self.stack.push(Value::Num(-num)),
+                    _ => return Err(self.error(ErrorCode::OperandNotNumber, String::from("Operand must be a number."))),
+                },
+                OpCode::Print => println!("{}", self.pop()),
+                OpCode::Jump => {
+                    let offset = self.read_u16() as usize;
+                    self.frame_mut().ip += offset;
+                }
+                OpCode::JumpIfFalse => {
+                    let offset = self.read_u16() as usize;
+                    if Vm::is_falsey(self.peek(0)) {
+                        self.frame_mut().ip += offset;
+                    }
+                }
+                OpCode::Loop => {
+                    let offset = self.read_u16() as usize;
+                    self.frame_mut().ip -= offset;
+                }
+                OpCode::Call => {
+                    let arg_count = self.read_byte() as usize;
+                    self.call_value(self.peek(arg_count).clone(), arg_count)?;
+                }
+                OpCode::Closure => {
+                    let Constant::Function(function) = self.read_constant() else {
+                        unreachable!("OpCode::Closure always refers to a function constant.")
+                    };
+
+                    let mut upvalues = Vec::with_capacity(function.upvalue_count);
+                    for _ in 0..function.upvalue_count {
+                        let is_local = self.read_byte() == 1;
+                        let index = self.read_byte() as usize;
+                        let upvalue = if is_local {
+                            self.capture_upvalue(self.frame().slots + index)
+                        } else {
+                            self.frame().closure.upvalues[index]
+                        };
+                        upvalues.push(upvalue);
+                    }
+
+                    self.stack.push(Value::Closure(Rc::new(Closure { function, upvalues })));
+                }
+                OpCode::CloseUpvalue => {
+                    self.close_upvalues(self.stack.len() - 1);
+                    self.pop();
+                }
+                OpCode::Return => {
+                    let result = self.pop();
+                    let frame = self.frames.pop().expect("Returned without a call frame.");
+                    self.close_upvalues(frame.slots);
+                    self.stack.truncate(frame.slots);
+
+                    if self.frames.is_empty() {
+                        return Ok(());
+                    }
+                    self.stack.push(result);
+                }
+                OpCode::Class => {
+                    let name = self.read_string();
+                    let class = Class { name, methods: HashMap::new() };
+                    self.stack.push(Value::Class(Rc::new(RefCell::new(class))));
+                }
+                OpCode::Inherit => {
+                    let Value::Class(superclass) = self.peek(1).clone() else {
+                        return Err(self.error(ErrorCode::SuperclassNotClass, String::from("Superclass must be a class.")));
+                    };
+                    let Value::Class(subclass) = self.pop() else {
+                        return Err(self.error(ErrorCode::InvalidBytecode, String::from("Inherit without a class.")));
+                    };
+
+                    // Copy-down inheritance: methods declared afterwards override these.
+                    let methods = superclass.borrow().methods.clone();
+                    subclass.borrow_mut().methods.extend(methods);
+                }
+                OpCode::Method => {
+                    let name = self.read_string();
+                    let (Value::Closure(method), Value::Class(class)) = (self.pop(), self.peek(0)) else {
+                        return Err(self.error(ErrorCode::InvalidBytecode, String::from("Method declared outside a class.")));
+                    };
+                    class.borrow_mut().methods.insert(name, method);
+                }
+            }
+        }
+    }
+
+    fn call_value(&mut self, callee: Value, arg_count: usize) -> Result<(), VmError> {
+        match callee {
+            Value::Closure(closure) => self.call(closure, arg_count),
+            Value::BoundMethod(bound) => {
+                let receiver = self.stack.len() - arg_count - 1;
+                self.stack[receiver] = bound.receiver.clone();
+                self.call(Rc::clone(&bound.method), arg_count)
+            }
+            Value::Class(class) => {
+                let receiver = self.stack.len() - arg_count - 1;
+                let fields = self.allocate(Fields::default());
+                self.stack[receiver] = Value::Instance(Instance { class: Rc::clone(&class), fields });
+
+                let initializer = class.borrow().methods.get(&Symbol::intern("init")).cloned();
+                match initializer {
+                    Some(initializer) => self.call(initializer, arg_count),
+                    None if arg_count != 0 => {
+                        Err(self.error(ErrorCode::WrongArity, format!("Expected 0 arguments but got {arg_count}.")))
+                    }
+                    None => Ok(()),
+                }
+            }
+            _ => Err(self.error(ErrorCode::NotCallable, String::from("Can only call functions and classes."))),
+        }
+    }
+
+    fn call(&mut self, closure: Rc<Closure>, arg_count: usize) -> Result<(), VmError> {
+        if arg_count != closure.function.arity {
+            return Err(self.error(ErrorCode::WrongArity, format!(
+                "Expected {} arguments but got {}.",
+                closure.function.arity, arg_count
+            )));
+        }
+
+        if self.frames.len() == FRAMES_MAX {
+            return Err(self.error(ErrorCode::StackOverflow, String::from("Stack overflow.")));
+        }
+
+        let slots = self.stack.len() - arg_count - 1;
+        self.frames.push(CallFrame { closure, ip: 0, slots });
+        Ok(())
+    }
+
+    /// Replaces the instance on top of the stack with its method `name` bound to it.
+    fn bind_method(&mut self, class: &Rc<RefCell<Class>>, name: &Symbol) -> Result<(), VmError> {
+        let Some(method) = class.borrow().methods.get(name).cloned() else {
+            return Err(self.error(ErrorCode::UndefinedProperty, format!("Undefined property '{name}'.")));
+        };
+
+        let receiver = self.pop();
+        self.stack.push(Value::BoundMethod(Rc::new(BoundMethod { receiver, method })));
+        Ok(())
+    }
+
+    fn capture_upvalue(&mut self, slot: usize) -> Gc<Upvalue> {
+        let position = self.open_upvalues.iter().position(|&upvalue| self.open_slot(upvalue) >= slot);
+
+        if let Some(index) = position {
+            let existing = self.open_upvalues[index];
+            if self.open_slot(existing) == slot {
+                return existing;
+            }
+        }
+
+        let upvalue = self.allocate(Upvalue::Open(slot));
+        let index = position.unwrap_or(self.open_upvalues.len());
+        self.open_upvalues.insert(index, upvalue);
+        upvalue
+    }
+
+    /// Moves every captured variable at or above `last` off the stack.
+    fn close_upvalues(&mut self, last: usize) {
+        while let Some(&upvalue) = self.open_upvalues.last() {
+            let slot = self.open_slot(upvalue);
+            if slot < last {
+                break;
+            }
+
+            *self.heap.get_mut(upvalue) = Upvalue::Closed(self.stack[slot].clone());
+            self.open_upvalues.pop();
+        }
+    }
+
+    fn open_slot(&self, upvalue: Gc<Upvalue>) -> usize {
+        match self.heap.get(upvalue) {
+            Upvalue::Open(slot) => *slot,
+            Upvalue::Closed(_) => unreachable!("Closed upvalues leave the open list."),
+        }
+    }
+
+    /// Moves `value` onto the heap, collecting first if the heap is due.
+    /// Everything the caller still needs must be reachable from the roots.
+    fn allocate<T: Managed>(&mut self, value: T) -> Gc<T> {
+        if self.heap.should_collect() {
+            self.collect_garbage();
+        }
+        self.heap.alloc(value)
+    }
+
+    fn collect_garbage(&mut self) {
+        let mut tracer = Tracer::default();
+        self.stack.iter().for_each(|value| value.trace(&mut tracer));
+        self.globals.values().for_each(|value| value.trace(&mut tracer));
+        self.frames.iter().for_each(|frame| frame.closure.trace(&mut tracer));
+        self.open_upvalues.iter().for_each(|&upvalue| tracer.mark(upvalue));
+        self.heap.collect(tracer);
+    }
+
+    fn trace_instruction(&self) {
+        let stack: String = self.stack.iter().map(|value| format!("[ {value} ]")).collect();
+        eprintln!("          {stack}");
+
+        let frame = self.frame();
+        let (instruction, _) = debug::disassemble_instruction(&frame.closure.function.chunk, frame.ip);
+        eprintln!("{instruction}");
+    }
+
+    fn arithmetic(&mut self, op: impl Fn(f64, f64) -> f64) -> Result<(), VmError> {
+        match (self.pop(), self.pop()) {
+            (Value::Num(b), Value::Num(a)) => {
+                self.stack.push(Value::Num(op(a, b)));
+                Ok(())
+            }
+            _ => Err(self.error(ErrorCode::OperandsNotNumbers, String::from("Operands must be two numbers."))),
+        }
+    }
+
+    fn comparison(&mut self, op: impl Fn(f64, f64) -> bool) -> Result<(), VmError> {
+        match (self.pop(), self.pop()) {
+            (Value::Num(b), Value::Num(a)) => {
+                self.stack.push(Value::Bool(op(a, b)));
+                Ok(())
+            }
+            _ => Err(self.error(ErrorCode::OperandsNotNumbers, String::from("Operands must be two numbers."))),
+        }
+    }
+
+    fn is_falsey(value: &Value) -> bool {
+        matches!(value, Value::Nil | Value::Bool(false))
+    }
+
+    fn values_equal(a: &Value, b: &Value) -> bool {
+        match (a, b) {
+            (Value::Nil, Value::Nil) => true,
+            (Value::Bool(a), Value::Bool(b)) => a == b,
+            (Value::Num(a), Value::Num(b)) => a == b,
+            (Value::Str(a), Value::Str(b)) => a == b,
+            (Value::Closure(a), Value::Closure(b)) => Rc::ptr_eq(a, b),
+            (Value::Class(a), Value::Class(b)) => Rc::ptr_eq(a, b),
+            (Value::Instance(a), Value::Instance(b)) => a.fields == b.fields,
+            (Value::BoundMethod(a), Value::BoundMethod(b)) => Rc::ptr_eq(a, b),
+            _ => false,
+        }
+    }
+
+    fn frame(&self) -> &CallFrame {
+        self.frames.last().expect("No active call frame.")
+    }
+
+    fn frame_mut(&mut self) -> &mut CallFrame {
+        self.frames.last_mut().expect("No active call frame.")
+    }
+
+    fn read_byte(&mut self) -> u8 {
+        let frame = self.frame_mut();
+        let byte = frame.closure.function.chunk.code[frame.ip];
+        frame.ip += 1;
+        byte
+    }
+
+    fn read_u16(&mut self) -> u16 {
+        let frame = self.frame_mut();
+        let value = frame.closure.function.chunk.read_u16(frame.ip);
+        frame.ip += 2;
+        value
+    }
+
+    fn read_constant(&mut self) -> Constant {
+        let index = self.read_u16() as usize;
+        self.frame().closure.function.chunk.constants[index].clone()
+    }
+
+    fn read_string(&mut self) -> Symbol {
+        match self.read_constant() {
+            Constant::Str(s) => s,
+            _ => unreachable!("Names are always string constants."),
+        }
+    }
+
+    fn peek(&self, distance: usize) -> &Value {
+        &self.stack[self.stack.len() - 1 - distance]
+    }
+
+    fn pop(&mut self) -> Value {
+        self.stack.pop().expect("Value stack underflow.")
+    }
+
+    /// The active calls, innermost first, each at the line it has reached.
+    fn trace(&self) -> Vec<TraceFrame> {
+        self.frames
+            .iter()
+            .rev()
+            .map(|frame| TraceFrame {
+                function: frame.closure.function.name.clone().unwrap_or_else(|| String::from("script")),
+                line: frame.closure.function.chunk.lines[frame.ip - 1],
+            })
+            .collect()
+    }
+
+    /// Errors raised before any frame has run, such as a script that can't
+    /// be called, have no line and are reported at line 0.
+    fn error(&self, code: ErrorCode, message: String) -> VmError {
+        let line = self.frames.last().map_or(0, |frame| frame.closure.function.chunk.lines[frame.ip - 1]);
+        VmError { code, message, line }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::Compiler;
+    use crate::parser::Parser;
+    use crate::scanner::Scanner;
+
+    fn run(source: &str) -> Vm {
+        run_with(source, GcConfig::default())
+    }
+
+    fn run_with(source: &str, config: GcConfig) -> Vm {
+        let tokens = Scanner::new(source.as_bytes(), &Reporter::default()).scan_tokens().expect("test program should scan");
+        let statements = Parser::new(tokens, &Reporter::default()).parse().expect("test program should parse");
+        let Ok(function) = Compiler::new(&Reporter::default()).compile(&statements) else { panic!("test program should compile") };
+
+        let mut vm = Vm::with_gc_config(config);
+        assert!(vm.interpret(function, &Reporter::default()).is_ok(), "test program should run");
+        vm
+    }
+
+    fn global(vm: &Vm, name: &str) -> String {
+        vm.globals[&Symbol::intern(name)].to_string()
+    }
+
+    #[test]
+    fn collector_frees_unreachable_cycles() {
+        let mut vm = run("
+            class Node {}
+            for (var i = 0; i < 100; i = i + 1) {
+                var a = Node();
+                var b = Node();
+                a.other = b;
+                b.other = a;
+                fun recurse() { return recurse; }
+            }
+            var kept = Node();
+            kept.me = kept;
+        ");
+
+        vm.collect_garbage();
+
+        // The fields of `kept`.
+        assert_eq!(vm.heap.live(), 1);
+        assert!(matches!(&vm.globals[&Symbol::intern("kept")], Value::Instance(kept) if vm.heap.get(kept.fields).values.len() == 1));
+    }
+
+    #[test]
+    fn stress_mode_keeps_values_in_flight_alive() {
+        let config = GcConfig { stress: true, ..GcConfig::default() };
+        let vm = run_with("
+            class Box {
+                init(value) { this.value = value; }
+                get() { return this.value; }
+            }
+            fun pair(a, b) {
+                fun sum() { return a.get() + b.get(); }
+                return sum;
+            }
+
+            var sum = pair(Box(1), Box(2))();
+            var bound = Box(\"kept\").get;
+            var called = bound();
+        ", config);
+
+        assert_eq!(global(&vm, "sum"), "3");
+        assert_eq!(global(&vm, "called"), "kept");
+    }
+
+    #[test]
+    fn a_script_that_cannot_be_called_is_a_runtime_error() {
+        let mut function = Function { arity: 1, ..Function::default() };
+        function.chunk.write(OpCode::Nil as u8, 1);
+        function.chunk.write(OpCode::Return as u8, 1);
+
+        let mut vm = Vm::new();
+        assert!(vm.interpret(Rc::new(function), &Reporter::default()).is_err());
+        assert!(vm.stack.is_empty() && vm.frames.is_empty());
+    }
+}
@@ -0,0 +1,234 @@
+//! Runs every script under `tests/lox` through both backends, through each
+//! again with a collection before every allocation, and through the VM again
+//! after a round trip via `--compile`. The output is checked against the
+//! script's annotations:
+//!
+//! - `// expect: <line>` — the next line printed to stdout.
+//! - `// expect runtime error: <message>` — the run stops with this error,
+//!   reported at the annotated line (`--> file:line:column` on the tree
+//!   interpreter, `--> file:line` on the VM).
+//! - `// expect trace: <function>:<line>` — in order, the rows of the runtime
+//!   error's stack trace; a folded run of frames is written as it's printed.
+//! - `// expect compile error: <message>` — this error is reported at the
+//!   annotated line before anything runs. Every annotated error must be
+//!   reported, in order, and no others.
+//!
+//! rlox must exit with 0 after a clean run, 65 after compile errors and 70
+//! after a runtime error.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+const BACKENDS: [&str; 5] = ["tree", "gc-stress", "vm", "vm-gc-stress", "compiled"];
+
+struct Expectations {
+    output: Vec<String>,
+    runtime_error: Option<(String, usize)>,
+    compile_errors: Vec<(String, usize)>,
+    trace: Vec<String>,
+}
+
+fn parse_expectations(source: &str) -> Expectations {
+    let mut output = Vec::new();
+    let mut runtime_error = None;
+    let mut compile_errors = Vec::new();
+    let mut trace = Vec::new();
+
+    for (index, line) in source.lines().enumerate() {
+        if let Some((_, expected)) = line.split_once("// expect: ") {
+            output.push(expected.trim_end().to_string());
+        } else if line.trim_end().ends_with("// expect:") {
+            output.push(String::new());
+        } else if let Some((_, message)) = line.split_once("// expect runtime error: ") {
+            runtime_error = Some((message.trim_end().to_string(), index + 1));
+        } else if let Some((_, message)) = line.split_once("// expect compile error: ") {
+            compile_errors.push((message.trim_end().to_string(), index + 1));
+        } else if let Some((_, row)) = line.split_once("// expect trace: ") {
+            trace.push(row.trim_end().to_string());
+        }
+    }
+
+    Expectations { output, runtime_error, compile_errors, trace }
+}
+
+fn check(script: &str) {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/lox").join(script);
+    let source = fs::read_to_string(&path).expect("conformance script should be readable");
+    let expected = parse_expectations(&source);
+
+    for backend in BACKENDS {
+        let output = run(backend, &path);
+
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let printed: Vec<&str> = stdout.lines().collect();
+
+        assert_eq!(printed, expected.output, "{script}: stdout differs on the {backend} backend");
+
+        let status = if !expected.compile_errors.is_empty() {
+            65
+        } else if expected.runtime_error.is_some() {
+            70
+        } else {
+            0
+        };
+        assert_eq!(output.status.code(), Some(status), "{script}: wrong exit status on the {backend} backend");
+
+        if !expected.compile_errors.is_empty() {
+            let lines: Vec<&str> = stderr.lines().collect();
+            let reported: Vec<(String, usize)> = lines
+                .iter()
+                .enumerate()
+                .filter_map(|(index, text)| {
+                    let message = text.strip_prefix("error: ")?;
+                    let line = reported_line(lines.get(index + 1)?, true)?;
+                    Some((message.to_string(), line))
+                })
+                .collect();
+            assert_eq!(reported, expected.compile_errors, "{script}: compile errors differ on the {backend} backend");
+            continue;
+        }
+
+        match &expected.runtime_error {
+            Some((message, line)) => {
+                let header = format!("error: {message}");
+                let location = stderr.lines().skip_while(|text| *text != header).nth(1);
+                assert_eq!(
+                    location.and_then(|location| reported_line(location, matches!(backend, "tree" | "gc-stress"))),
+                    Some(*line),
+                    "{script}: expected runtime error {header:?} at line {line} on the {backend} backend, got {stderr:?}"
+                );
+            }
+            None => assert!(stderr.is_empty(), "{script}: unexpected stderr on the {backend} backend: {stderr:?}"),
+        }
+
+        if !expected.trace.is_empty() {
+            assert_eq!(reported_trace(&stderr), expected.trace, "{script}: stack trace differs on the {backend} backend");
+        }
+    }
+}
+
+/// The rows of a stack trace, with `at f (file:3)` shortened to `f:3`.
+fn reported_trace(stderr: &str) -> Vec<String> {
+    stderr
+        .lines()
+        .skip_while(|line| !line.starts_with("stack trace"))
+        .skip(1)
+        .map(|row| {
+            let row = row.trim();
+            match row.strip_prefix("at ").and_then(|row| row.split_once(" (")) {
+                Some((function, location)) => {
+                    let line = location.trim_end_matches(')').rsplit(':').next().unwrap_or_default();
+                    format!("{function}:{line}")
+                }
+                None => row.to_string(),
+            }
+        })
+        .collect()
+}
+
+/// Reads the line number out of a diagnostic's `--> file:line[:column]` row.
+fn reported_line(location: &str, has_column: bool) -> Option<usize> {
+    let location = location.trim().strip_prefix("--> ")?;
+    location.rsplit(':').nth(usize::from(has_column))?.parse().ok()
+}
+
+fn rlox(args: &[&std::ffi::OsStr]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_rlox")).args(args).output().expect("rlox should run")
+}
+
+fn run(backend: &str, path: &Path) -> Output {
+    match backend {
+        "gc-stress" => return rlox(&["--backend=tree".as_ref(), "--gc-stress".as_ref(), path.as_ref()]),
+        "vm-gc-stress" => return rlox(&["--backend=vm".as_ref(), "--gc-stress".as_ref(), path.as_ref()]),
+        "compiled" => {}
+        _ => return rlox(&[format!("--backend={backend}").as_ref(), path.as_ref()]),
+    }
+
+    let name = path.strip_prefix(env!("CARGO_MANIFEST_DIR")).unwrap_or(path).to_string_lossy().replace(['/', '\\'], "_");
+    let compiled: PathBuf = std::env::temp_dir().join(format!("rlox-{}-{name}c", std::process::id()));
+
+    let compile = rlox(&[format!("--compile={}", compiled.display()).as_ref(), path.as_ref()]);
+    if !compile.status.success() {
+        return compile;
+    }
+
+    let output = rlox(&[compiled.as_ref()]);
+    let _ = fs::remove_file(&compiled);
+    output
+}
+
+#[test]
+fn json_error_format() {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/lox/errors/undefined_variable.lox");
+
+    for (backend, start) in [("tree", r#"{"line":2,"column":7}"#), ("vm", r#"{"line":2,"column":null}"#)] {
+        let output = rlox(&[format!("--backend={backend}").as_ref(), "--error-format=json".as_ref(), path.as_ref()]);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let lines: Vec<&str> = stderr.lines().collect();
+
+        assert_eq!(lines.len(), 1, "expected one JSON object on the {backend} backend, got {stderr:?}");
+        let object = lines[0];
+        assert!(object.starts_with(r#"{"code":"E0405","severity":"error","message":"Undefined variable 'missing'.""#), "{object}");
+        assert!(object.contains(&format!(r#""start":{start}"#)), "{backend}: {object}");
+    }
+}
+
+#[test]
+fn missing_script_exits_66() {
+    let output = rlox(&["tests/lox/does_not_exist.lox".as_ref()]);
+    assert_eq!(output.status.code(), Some(66));
+}
+
+#[test]
+fn bytecode_flags_reject_the_tree_backend() {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/lox/arithmetic.lox");
+
+    for flag in ["--trace", "--disassemble", "--compile=out.loxc"] {
+        let output = rlox(&["--backend=tree".as_ref(), flag.as_ref(), path.as_ref()]);
+        assert_eq!(output.status.code(), Some(64), "{flag}");
+        assert!(output.stdout.is_empty(), "{flag} ran the script anyway");
+    }
+}
+
+macro_rules! conformance {
+    ( $( $name:ident => $script:literal ),* $(,)? ) => {
+        $(
+            #[test]
+            fn $name() {
+                check($script);
+            }
+        )*
+    };
+}
+
+conformance!(
+    arithmetic => "arithmetic.lox",
+    strings => "strings.lox",
+    variables => "variables.lox",
+    scope => "scope.lox",
+    control_flow => "control_flow.lox",
+    logical => "logical.lox",
+    functions => "functions.lox",
+    closures => "closures.lox",
+    classes => "classes.lox",
+    inheritance => "inheritance.lox",
+    exponent => "exponent.lox",
+    undefined_variable => "errors/undefined_variable.lox",
+    undefined_assignment => "errors/undefined_assignment.lox",
+    operand_types => "errors/operand_types.lox",
+    negate_string => "errors/negate_string.lox",
+    divide_by_zero => "errors/divide_by_zero.lox",
+    call_non_callable => "errors/call_non_callable.lox",
+    wrong_arity => "errors/wrong_arity.lox",
+    class_arity => "errors/class_arity.lox",
+    undefined_property => "errors/undefined_property.lox",
+    property_on_non_instance => "errors/property_on_non_instance.lox",
+    superclass_not_class => "errors/superclass_not_class.lox",
+    error_in_function => "errors/error_in_function.lox",
+    stack_trace => "errors/stack_trace.lox",
+    stack_overflow => "errors/stack_overflow.lox",
+    scan_errors => "errors/scan_errors.lox",
+    syntax_errors => "errors/syntax_errors.lox",
+);
@@ -0,0 +1,303 @@
+//! Drives rlox through its library API, the way a host program embeds it.
+
+use std::cell::{Cell, RefCell};
+use std::fmt;
+use std::rc::Rc;
+
+use rlox::bytecode;
+use rlox::syntax::{self, Stmt};
+use rlox::{Arity, Error, GcConfig, Lox, Reporter, Rooted, Symbol, Tracer, UserData, Value};
+
+fn number(value: Option<Rooted>) -> f64 {
+    match value.as_deref() {
+        Some(Value::Num(number)) => *number,
+        other => panic!("expected a number, got {other:?}"),
+    }
+}
+
+#[test]
+fn eval_returns_the_value_of_a_trailing_expression() {
+    let mut lox = Lox::new();
+    assert!(matches!(lox.eval("1 + 2;").as_deref(), Ok(Value::Num(sum)) if *sum == 3.0));
+    assert!(matches!(lox.eval("var unused = 1;").as_deref(), Ok(Value::Nil)));
+    assert!(matches!(lox.eval("").as_deref(), Ok(Value::Nil)));
+}
+
+#[test]
+fn globals_flow_between_host_and_script() {
+    let mut lox = Lox::new();
+    lox.set_global("base", Value::Num(10.0));
+    lox.set_global("name", Value::Str(Symbol::intern("rlox")));
+
+    lox.eval("var scaled = base * 3; var greeting = \"hi \" + name;").expect("script should run");
+
+    assert_eq!(number(lox.get_global("scaled")), 30.0);
+    assert!(matches!(lox.get_global("greeting").as_deref(), Some(Value::Str(text)) if *text == "hi rlox"));
+    assert!(lox.get_global("missing").is_none());
+}
+
+#[test]
+fn state_persists_across_calls() {
+    let mut lox = Lox::new();
+    lox.eval("class Counter { init() { this.n = 0; } bump() { this.n = this.n + 1; return this.n; } }").unwrap();
+    lox.eval("var counter = Counter();").unwrap();
+    lox.eval("counter.bump();").unwrap();
+
+    assert!(matches!(lox.eval("counter.bump();").as_deref(), Ok(Value::Num(n)) if *n == 2.0));
+}
+
+#[test]
+fn values_held_by_the_host_survive_collections() {
+    let mut lox = Lox::with_gc_config(GcConfig { stress: true, ..GcConfig::default() });
+    lox.eval("class Foo { init(n) { this.n = n; } }").unwrap();
+
+    let kept = lox.eval("Foo(\"first\");").expect("script should run");
+    lox.eval("var other = Foo(\"second\");").unwrap();
+    lox.set_global("kept", (*kept).clone());
+
+    assert!(matches!(lox.eval("kept.n;").as_deref(), Ok(Value::Str(n)) if *n == "first"));
+    assert!(matches!(lox.eval("kept == other;").as_deref(), Ok(Value::Boolean(false))));
+}
+
+#[test]
+fn failures_say_which_phase_failed() {
+    let mut lox = Lox::new();
+    assert!(matches!(lox.eval("var = 1;"), Err(Error::Compile)));
+    assert!(matches!(lox.eval("nil + 1;"), Err(Error::Runtime)));
+    assert!(matches!(lox.run_file("tests/lox/does_not_exist.lox"), Err(Error::Io(_))));
+
+    // The session is still usable afterwards.
+    assert!(matches!(lox.eval("1;").as_deref(), Ok(Value::Num(_))));
+}
+
+#[test]
+fn run_file_defines_globals() {
+    let mut lox = Lox::new();
+    lox.run_file(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/lox/closures.lox")).expect("script should run");
+    assert!(matches!(lox.get_global("makeCounter").as_deref(), Some(Value::Function(_))));
+    assert!(matches!(lox.eval("first();").as_deref(), Ok(Value::Num(n)) if *n == 3.0));
+}
+
+#[test]
+fn natives_are_called_like_lox_functions() {
+    let mut lox = Lox::new();
+    lox.define_native("double", Arity::Fixed(1), |arguments| match arguments {
+        [Value::Num(number)] => Ok(Value::Num(number * 2.0)),
+        _ => Err(String::from("Can only double numbers.")),
+    });
+    lox.define_native("count", Arity::Variadic, |arguments| Ok(Value::Num(arguments.len() as f64)));
+
+    assert!(matches!(lox.eval("fun twice(f, x) { return f(f(x)); } twice(double, 3);").as_deref(), Ok(Value::Num(n)) if *n == 12.0));
+    assert!(matches!(lox.eval("count() + count(1, nil, \"x\");").as_deref(), Ok(Value::Num(n)) if *n == 3.0));
+    assert!(matches!(lox.eval("var f = double; f == double;").as_deref(), Ok(Value::Boolean(true))));
+}
+
+#[test]
+fn natives_can_hand_back_rooted_values_they_captured() {
+    let mut lox = Lox::with_gc_config(GcConfig { stress: true, ..GcConfig::default() });
+    lox.eval("class Config { init(name) { this.name = name; } }").unwrap();
+
+    let config = lox.eval("Config(\"main\");").expect("script should run");
+    lox.define_native("config", Arity::Fixed(0), move |_| Ok((*config).clone()));
+    lox.eval("for (var i = 0; i < 10; i = i + 1) { var junk = Config(\"junk\"); }").unwrap();
+
+    assert!(matches!(lox.eval("config().name;").as_deref(), Ok(Value::Str(name)) if *name == "main"));
+    assert!(matches!(lox.eval("config() == config();").as_deref(), Ok(Value::Boolean(true))));
+}
+
+#[test]
+fn native_errors_and_arity_mismatches_are_runtime_errors() {
+    let mut lox = Lox::new();
+    lox.define_native("double", Arity::Fixed(1), |arguments| match arguments {
+        [Value::Num(number)] => Ok(Value::Num(number * 2.0)),
+        _ => Err(String::from("Can only double numbers.")),
+    });
+
+    assert!(matches!(lox.eval("double(\"x\");"), Err(Error::Runtime)));
+    assert!(matches!(lox.eval("double(1, 2);"), Err(Error::Runtime)));
+}
+
+#[test]
+fn compiled_files_are_rejected_once_natives_or_userdata_are_registered() {
+    let function = bytecode::compile("print 1;", &Reporter::default()).expect("script should compile");
+    let path = std::env::temp_dir().join(format!("rlox-embedding-{}.loxc", std::process::id()));
+    std::fs::write(&path, bytecode::serialize(&function)).expect("compiled file should be written");
+
+    let mut lox = Lox::new();
+    let plain = lox.run_file(&path);
+    lox.define_native("double", Arity::Fixed(1), |arguments| Ok(arguments[0].clone()));
+    let with_native = lox.run_file(&path);
+
+    let mut lox = Lox::new();
+    lox.set_global("meter", Value::UserData(Rc::new(Meter { total: Cell::new(0.0), step: Cell::new(1.0) })));
+    let with_userdata = lox.run_file(&path);
+    let _ = std::fs::remove_file(&path);
+
+    assert!(plain.is_ok());
+    assert!(matches!(with_native, Err(Error::HostBindings)));
+    assert!(matches!(with_userdata, Err(Error::HostBindings)));
+}
+
+/// A host-side counter with a settable `step`.
+struct Meter {
+    total: Cell<f64>,
+    step: Cell<f64>,
+}
+
+impl fmt::Display for Meter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "<meter {}>", self.total.get())
+    }
+}
+
+impl UserData for Meter {
+    fn type_name(&self) -> &str {
+        "Meter"
+    }
+
+    fn get(&self, name: &str) -> Option<Value> {
+        match name {
+            "total" => Some(Value::Num(self.total.get())),
+            "step" => Some(Value::Num(self.step.get())),
+            _ => None,
+        }
+    }
+
+    fn set(&self, name: &str, value: Value) -> Result<(), String> {
+        match (name, value) {
+            ("step", Value::Num(step)) => {
+                self.step.set(step);
+                Ok(())
+            }
+            ("step", _) => Err(String::from("Step must be a number.")),
+            _ => Err(format!("Meter has no settable field '{name}'.")),
+        }
+    }
+
+    fn method(&self, name: &str) -> Option<Arity> {
+        (name == "tick").then_some(Arity::Fixed(0))
+    }
+
+    fn call(&self, _name: &str, _arguments: &[Value]) -> Result<Value, String> {
+        self.total.set(self.total.get() + self.step.get());
+        Ok(Value::Num(self.total.get()))
+    }
+}
+
+fn meter() -> Rc<Meter> {
+    Rc::new(Meter { total: Cell::new(0.0), step: Cell::new(1.0) })
+}
+
+#[test]
+fn userdata_exposes_fields_and_methods() {
+    let mut lox = Lox::new();
+    let meter = meter();
+    lox.set_global("meter", Value::UserData(meter.clone()));
+
+    lox.eval("meter.tick(); meter.step = 5; var tick = meter.tick; tick();").expect("script should run");
+
+    assert_eq!(meter.total.get(), 6.0);
+    assert_eq!(number(lox.eval("meter.total;").ok()), 6.0);
+    assert!(matches!(lox.eval("meter == meter;").as_deref(), Ok(Value::Boolean(true))));
+    assert_eq!(lox.eval("meter;").expect("script should run").to_string(), "<meter 6>");
+}
+
+#[test]
+fn userdata_hook_failures_are_runtime_errors() {
+    let mut lox = Lox::new();
+    lox.set_global("meter", Value::UserData(meter()));
+
+    assert!(matches!(lox.eval("meter.missing;"), Err(Error::Runtime)));
+    assert!(matches!(lox.eval("meter.total = 3;"), Err(Error::Runtime)));
+    assert!(matches!(lox.eval("meter.step = \"fast\";"), Err(Error::Runtime)));
+    assert!(matches!(lox.eval("meter.tick(1);"), Err(Error::Runtime)));
+}
+
+/// A host-side box that scripts can put any value in.
+struct Slot {
+    item: RefCell<Value>,
+}
+
+impl fmt::Display for Slot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "<slot>")
+    }
+}
+
+impl UserData for Slot {
+    fn type_name(&self) -> &str {
+        "Slot"
+    }
+
+    fn get(&self, name: &str) -> Option<Value> {
+        (name == "item").then(|| self.item.borrow().clone())
+    }
+
+    fn set(&self, _name: &str, value: Value) -> Result<(), String> {
+        *self.item.borrow_mut() = value;
+        Ok(())
+    }
+
+    fn method(&self, name: &str) -> Option<Arity> {
+        (name == "take").then_some(Arity::Fixed(0))
+    }
+
+    fn call(&self, _name: &str, _arguments: &[Value]) -> Result<Value, String> {
+        Ok(self.item.replace(Value::Nil))
+    }
+
+    fn trace(&self, tracer: &mut Tracer) {
+        tracer.value(&self.item.borrow());
+    }
+}
+
+#[test]
+fn values_stored_in_userdata_are_traced() {
+    let mut lox = Lox::with_gc_config(GcConfig { stress: true, ..GcConfig::default() });
+    lox.set_global("slot", Value::UserData(Rc::new(Slot { item: RefCell::new(Value::Nil) })));
+    lox.eval("class Foo { init(n) { this.n = n; } } slot.item = Foo(\"kept\");").unwrap();
+    lox.eval("for (var i = 0; i < 10; i = i + 1) { var junk = Foo(\"junk\"); }").unwrap();
+
+    assert!(matches!(lox.eval("slot.item.n;").as_deref(), Ok(Value::Str(n)) if *n == "kept"));
+}
+
+#[test]
+fn bound_userdata_methods_keep_their_receiver_traced() {
+    let mut lox = Lox::with_gc_config(GcConfig { stress: true, ..GcConfig::default() });
+    lox.set_global("slot", Value::UserData(Rc::new(Slot { item: RefCell::new(Value::Nil) })));
+    lox.eval("class Foo { init(n) { this.n = n; } } slot.item = Foo(\"kept\");").unwrap();
+    lox.eval("var take = slot.take; slot = nil;").unwrap();
+    lox.eval("for (var i = 0; i < 10; i = i + 1) { var junk = Foo(\"junk\"); }").unwrap();
+
+    assert!(matches!(lox.eval("take().n;").as_deref(), Ok(Value::Str(n)) if *n == "kept"));
+}
+
+#[test]
+fn runaway_recursion_is_a_runtime_error() {
+    let session = std::thread::Builder::new().stack_size(rlox::STACK_SIZE).spawn(|| {
+        let mut lox = Lox::new();
+        let overflowed = matches!(lox.eval("fun f(n) { if (n > 0) { { return f(n + 1) + 1; } } } f(1);"), Err(Error::Runtime));
+        (overflowed, matches!(lox.eval("fun g(n) { if (n > 0) return g(n - 1); return n; } g(500);").as_deref(), Ok(Value::Num(n)) if *n == 0.0))
+    });
+
+    let (overflowed, recovered) = session.expect("thread should start").join().expect("thread should not crash");
+    assert!(overflowed, "deep recursion should stop with a runtime error");
+    assert!(recovered, "the session should allow calls again afterwards");
+}
+
+#[test]
+fn syntax_errors_keep_the_declarations_that_parsed() {
+    let errors = syntax::parse("var a = 1 +;\nvar b = 2;\nprint (b;\nfun f() {}", &Reporter::default())
+        .expect_err("source with syntax errors should not parse");
+
+    let names: Vec<&str> = errors
+        .statements
+        .iter()
+        .map(|stmt| match stmt {
+            Stmt::Var(var) => var.name.lexeme.as_str(),
+            Stmt::Function(function) => function.name.lexeme.as_str(),
+            _ => "other",
+        })
+        .collect();
+    assert_eq!(names, ["b", "f"]);
+}